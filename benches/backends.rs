@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use vmem::{MmapFile, PageStore, VirtualMemory};
+
+const PAGE_SIZE: usize = 4096;
+// values in a page of 4096 bytes
+const DATA_SIZE: usize = 3640;
+const BUFFER_SIZE: usize = 64;
+const PAGES: [usize; 2] = [64, 4096];
+
+fn filled<S: PageStore>(swap_source: S, pages: usize) -> VirtualMemory<S> {
+    let mut vm = VirtualMemory::new(swap_source, PAGE_SIZE, BUFFER_SIZE);
+    for page in 0..pages {
+        vm.write(page * DATA_SIZE, page as u8);
+    }
+    vm.flush().unwrap();
+    vm
+}
+
+// cyclic scan over the swap file, misses once there are more pages than the buffer holds
+fn scan<S: PageStore>(c: &mut Criterion, name: &str, swap_source: impl Fn() -> S) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(1));
+
+    for pages in PAGES {
+        let mut vm = filled(swap_source(), pages);
+        let mut page = 0;
+        group.bench_with_input(BenchmarkId::from_parameter(pages), &pages, |b, _| {
+            b.iter(|| {
+                page = (page + 1) % pages;
+                vm.write(page * DATA_SIZE + 1, page as u8);
+                black_box(vm.read(page * DATA_SIZE))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn file(c: &mut Criterion) {
+    scan(c, "file", || tempfile::tempfile().unwrap());
+}
+
+fn mmap(c: &mut Criterion) {
+    scan(c, "mmap", || {
+        MmapFile::new(tempfile::tempfile().unwrap()).unwrap()
+    });
+}
+
+criterion_group!(benches, file, mmap);
+criterion_main!(benches);
@@ -0,0 +1,156 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use std::io::Cursor;
+use vmem::VirtualMemory;
+
+const PAGE_SIZE: usize = 72;
+// values in a page of 72 bytes
+const DATA_SIZE: usize = 64;
+const BUFFER_SIZES: [usize; 3] = [64, 1024, 16384];
+
+fn filled(buffer_size: usize, pages: usize) -> VirtualMemory<Cursor<Vec<u8>>> {
+    let mut vm = VirtualMemory::new(Cursor::new(Vec::new()), PAGE_SIZE, buffer_size);
+    for page in 0..pages {
+        vm.write(page * DATA_SIZE, page as u8);
+    }
+    vm
+}
+
+// page frame of the baseline buffer
+struct Frame {
+    index: usize,
+    last_access: u64,
+    values: Vec<u8>,
+}
+
+// the buffer the page table replaced: a `Vec` of frames found by a linear
+// scan, evicting by sorting the whole buffer by last access
+struct ScanBuffer {
+    frames: Vec<Frame>,
+    buffer_size: usize,
+    // pages evicted from the buffer
+    swap: Vec<Vec<u8>>,
+    clock: u64,
+}
+
+impl ScanBuffer {
+    fn filled(buffer_size: usize, pages: usize) -> ScanBuffer {
+        let mut buffer = ScanBuffer {
+            frames: Vec::new(),
+            buffer_size,
+            swap: vec![vec![0; DATA_SIZE]; pages],
+            clock: 0,
+        };
+        for page in 0..pages {
+            buffer.frame_mut(page).values[0] = page as u8;
+        }
+        buffer
+    }
+
+    fn read(&mut self, index: usize) -> u8 {
+        self.frame_mut(index / DATA_SIZE).values[index % DATA_SIZE]
+    }
+
+    fn frame_mut(&mut self, index: usize) -> &mut Frame {
+        self.clock += 1;
+        if !self.frames.iter().any(|frame| frame.index == index) {
+            if self.frames.len() == self.buffer_size {
+                self.frames
+                    .sort_by_key(|frame| std::cmp::Reverse(frame.last_access));
+                let oldest = self.frames.pop().unwrap();
+                self.swap[oldest.index] = oldest.values;
+            }
+            let values = self.swap[index].clone();
+            self.frames.push(Frame {
+                index,
+                last_access: 0,
+                values,
+            });
+        }
+
+        let clock = self.clock;
+        let frame = self
+            .frames
+            .iter_mut()
+            .find(|frame| frame.index == index)
+            .unwrap();
+        frame.last_access = clock;
+        frame
+    }
+}
+
+// every access finds its page in the buffer
+fn hits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hit");
+    group.throughput(Throughput::Elements(1));
+
+    for buffer_size in BUFFER_SIZES {
+        let mut vm = filled(buffer_size, buffer_size);
+        let mut page = 0;
+        group.bench_with_input(
+            BenchmarkId::new("page_table", buffer_size),
+            &buffer_size,
+            |b, &buffer_size| {
+                b.iter(|| {
+                    // stride through the buffer so every access touches another page
+                    page = (page + 7919) % buffer_size;
+                    black_box(vm.read(page * DATA_SIZE))
+                })
+            },
+        );
+
+        let mut buffer = ScanBuffer::filled(buffer_size, buffer_size);
+        let mut page = 0;
+        group.bench_with_input(
+            BenchmarkId::new("scan", buffer_size),
+            &buffer_size,
+            |b, &buffer_size| {
+                b.iter(|| {
+                    page = (page + 7919) % buffer_size;
+                    black_box(buffer.read(page * DATA_SIZE))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// every access evicts a page and loads another one
+fn evictions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eviction");
+    group.throughput(Throughput::Elements(1));
+
+    for buffer_size in BUFFER_SIZES {
+        let pages = buffer_size * 2;
+        let mut vm = filled(buffer_size, pages);
+        let mut page = 0;
+        group.bench_with_input(
+            BenchmarkId::new("page_table", buffer_size),
+            &buffer_size,
+            |b, _| {
+                b.iter(|| {
+                    // cyclic scan over twice the buffer always misses under LRU
+                    page = (page + 1) % pages;
+                    black_box(vm.read(page * DATA_SIZE))
+                })
+            },
+        );
+
+        let mut buffer = ScanBuffer::filled(buffer_size, pages);
+        let mut page = 0;
+        group.bench_with_input(
+            BenchmarkId::new("scan", buffer_size),
+            &buffer_size,
+            |b, _| {
+                b.iter(|| {
+                    page = (page + 1) % pages;
+                    black_box(buffer.read(page * DATA_SIZE))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hits, evictions);
+criterion_main!(benches);
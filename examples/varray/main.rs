@@ -1,9 +1,5 @@
-mod varray;
-mod varray_error;
-mod varray_item;
-
 use serde::{Deserialize, Serialize};
-use varray::VArray;
+use vmem::VArray;
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 struct Record {
@@ -11,22 +7,22 @@ struct Record {
     timestamp: i128,
 }
 
-impl varray_item::Item<'_> for [Record; 3] {}
-
-fn main() {
+fn main() -> vmem::Result<()> {
     let swap_file = tempfile::tempfile().unwrap();
 
-    // array: VArray<[Record; 3], File>
-    let mut array = VArray::new(swap_file, 4).unwrap();
-
     let r = Record {
         value: 913.08491,
         timestamp: 109247203947,
     };
 
-    for i in 0..100 {
-        array.write(i, [r, r, r]).unwrap();
+    // every element takes the same space, size it for the largest one
+    let element_size = bincode::serialized_size(&[r, r, r])? as usize;
+    let mut array: VArray<[Record; 3], _> = VArray::new(swap_file, element_size, 4)?;
+
+    for _ in 0..100 {
+        array.push(&[r, r, r])?;
     }
 
-    dbg! {array.read(99)};
+    dbg! {array.get(99)?};
+    Ok(())
 }
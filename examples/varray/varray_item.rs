@@ -1,3 +0,0 @@
-use serde::{Deserialize, Serialize};
-
-pub trait Item<'a>: Deserialize<'a> + Serialize {}
@@ -0,0 +1,422 @@
+use crate::error::{Error, Result};
+use crate::page_store::PageStore;
+use crate::virtual_memory::VirtualMemory;
+
+const SIGNATURE: &[u8; 4] = b"VAL1";
+// block of size class `c` takes 2^c bytes, header included
+const CLASSES: usize = 64;
+const MIN_CLASS: usize = 4;
+// signature, end of the last block, first free block of every class
+const META_SIZE: usize = SIGNATURE.len() + 8 + CLASSES * 8;
+// state, size class, 2 reserved bytes, length of the data
+const BLOCK_HEADER: usize = 8;
+const ALLOCATED: u8 = 1;
+const FREE: u8 = 2;
+// bytes zeroed or copied at once
+const CHUNK: usize = 4096;
+
+// Location of an allocation, stays valid until it is freed,
+// also after the swap source is reopened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Handle(u64);
+
+impl Handle {
+    // offset of the allocation, to store the handle elsewhere
+    pub fn to_raw(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_raw(raw: u64) -> Self {
+        Handle(raw)
+    }
+}
+
+// Allocator of variable-length byte blobs in the address space of
+// virtual memory. Blocks are rounded up to power of two size classes
+// and freed blocks are kept in one free list per class, linked through
+// the blocks themselves, so everything lives in the swap source.
+// Blocks are neither split nor merged, and every block is aligned to
+// its size, so a handle has to be a boundary of its class.
+#[derive(Debug)]
+pub struct Allocator<S>
+where
+    S: PageStore,
+{
+    vm: VirtualMemory<S>,
+    // first byte after the last block
+    end: u64,
+    // first free block of every class, offset + 1 or 0
+    free_heads: [u64; CLASSES],
+}
+
+impl<S> Allocator<S>
+where
+    S: PageStore,
+{
+    pub fn new(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        let vm = VirtualMemory::try_new(swap_source, page_size, buffer_size)?;
+        let mut allocator = Allocator {
+            vm,
+            // blocks of the smallest class start after the metadata
+            end: META_SIZE.next_multiple_of(1 << MIN_CLASS) as u64,
+            free_heads: [0; CLASSES],
+        };
+        allocator.write_meta()?;
+        Ok(allocator)
+    }
+
+    // reopen swap source created by `new`, keeping its allocations
+    pub fn open(swap_source: S, buffer_size: usize) -> Result<Self> {
+        let mut vm = VirtualMemory::open(swap_source, buffer_size)?;
+        let mut bytes = vec![0u8; META_SIZE];
+        vm.try_read_into(0, &mut bytes)?;
+        if &bytes[..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::InvalidConfig(
+                "Swap source doesn't hold an Allocator",
+            ));
+        }
+
+        let mut fields = bytes[SIGNATURE.len()..]
+            .chunks_exact(8)
+            .map(|field| u64::from_le_bytes(read(field, 0)));
+        let end = fields.next().expect("Allocator metadata is too short");
+        let mut free_heads = [0; CLASSES];
+        free_heads
+            .iter_mut()
+            .zip(fields)
+            .for_each(|(head, field)| *head = field);
+
+        Ok(Allocator {
+            vm,
+            end,
+            free_heads,
+        })
+    }
+
+    // reserve `len` zeroed bytes
+    pub fn alloc(&mut self, len: usize) -> Result<Handle> {
+        let class = class_of(len)?;
+        let offset = match self.free_heads[class] {
+            0 => {
+                // the space up to the next boundary of the class goes
+                // to the free lists of smaller classes, aligned in turn
+                let size = 1u64 << class;
+                while !self.end.is_multiple_of(size) {
+                    let gap = self.end.trailing_zeros() as usize;
+                    self.push_free(self.end, gap)?;
+                    self.end += 1 << gap;
+                }
+                let offset = self.end;
+                self.end += size;
+                offset
+            }
+            head => {
+                let offset = head - 1;
+                // free blocks keep the next one of their list after the header
+                let mut next = [0u8; 8];
+                self.read_bytes(offset + BLOCK_HEADER as u64, &mut next)?;
+                self.free_heads[class] = u64::from_le_bytes(next);
+                offset
+            }
+        };
+
+        self.write_header(offset, ALLOCATED, class, len)?;
+        self.zero(offset + BLOCK_HEADER as u64, len)?;
+        Ok(Handle(offset))
+    }
+
+    // return the block to the free list of its class
+    pub fn free(&mut self, handle: Handle) -> Result<()> {
+        let (class, _) = self.block(handle)?;
+        self.push_free(handle.0, class)
+    }
+
+    // resize the allocation, keeping its bytes up to the shorter length
+    // and zeroing new ones, returns the handle to use from now on
+    pub fn realloc(&mut self, handle: Handle, len: usize) -> Result<Handle> {
+        let (class, old_len) = self.block(handle)?;
+        if class_of(len)? == class {
+            if len > old_len {
+                self.zero(handle.0 + (BLOCK_HEADER + old_len) as u64, len - old_len)?;
+            }
+            self.write_header(handle.0, ALLOCATED, class, len)?;
+            return Ok(handle);
+        }
+
+        let new = self.alloc(len)?;
+        let mut chunk = vec![0u8; CHUNK];
+        let mut copied = 0;
+        while copied < old_len.min(len) {
+            let size = CHUNK.min(old_len.min(len) - copied);
+            let offset = (BLOCK_HEADER + copied) as u64;
+            self.read_bytes(handle.0 + offset, &mut chunk[..size])?;
+            self.write_bytes(new.0 + offset, &chunk[..size])?;
+            copied += size;
+        }
+        self.free(handle)?;
+        Ok(new)
+    }
+
+    // length of the allocation
+    pub fn len(&mut self, handle: Handle) -> Result<usize> {
+        Ok(self.block(handle)?.1)
+    }
+
+    pub fn read(&mut self, handle: Handle) -> Result<Vec<u8>> {
+        let (_, len) = self.block(handle)?;
+        let mut bytes = vec![0u8; len];
+        self.read_bytes(handle.0 + BLOCK_HEADER as u64, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    // overwrite bytes of the allocation starting at `offset`
+    pub fn write(&mut self, handle: Handle, offset: usize, bytes: &[u8]) -> Result<()> {
+        let (_, len) = self.block(handle)?;
+        if offset.checked_add(bytes.len()).is_none_or(|end| end > len) {
+            return Err(Error::IndexOutOfRange {
+                index: offset.saturating_add(bytes.len()),
+            });
+        }
+        self.write_bytes(handle.0 + (BLOCK_HEADER + offset) as u64, bytes)
+    }
+
+    // write every modified page and the free lists to the swap source
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_meta()?;
+        self.vm.flush()
+    }
+
+    fn write_meta(&mut self) -> Result<()> {
+        let mut bytes = Vec::with_capacity(META_SIZE);
+        bytes.extend_from_slice(SIGNATURE);
+        bytes.extend_from_slice(&self.end.to_le_bytes());
+        for head in self.free_heads {
+            bytes.extend_from_slice(&head.to_le_bytes());
+        }
+        self.vm.try_write_slice(0, &bytes)
+    }
+
+    // size class and length of the allocated block at `handle`
+    fn block(&mut self, handle: Handle) -> Result<(usize, usize)> {
+        let invalid = Error::InvalidHandle { handle: handle.0 };
+        if handle.0 < META_SIZE as u64 || handle.0 >= self.end {
+            return Err(invalid);
+        }
+
+        let mut header = [0u8; BLOCK_HEADER];
+        self.read_bytes(handle.0, &mut header)?;
+        let class = header[1] as usize;
+        if header[0] != ALLOCATED || !(MIN_CLASS..CLASSES).contains(&class) {
+            return Err(invalid);
+        }
+        // bytes inside a blob can look like a header, but only
+        // a block boundary is aligned to the size of its class
+        let size = 1u64 << class;
+        if !handle.0.is_multiple_of(size)
+            || handle.0.checked_add(size).is_none_or(|end| end > self.end)
+        {
+            return Err(invalid);
+        }
+        Ok((class, u32::from_le_bytes(read(&header, 4)) as usize))
+    }
+
+    // put the block at `offset` on the free list of `class`
+    fn push_free(&mut self, offset: u64, class: usize) -> Result<()> {
+        self.write_header(offset, FREE, class, 0)?;
+        let next = self.free_heads[class].to_le_bytes();
+        self.write_bytes(offset + BLOCK_HEADER as u64, &next)?;
+        self.free_heads[class] = offset + 1;
+        Ok(())
+    }
+
+    fn write_header(&mut self, offset: u64, state: u8, class: usize, len: usize) -> Result<()> {
+        let mut header = [0u8; BLOCK_HEADER];
+        header[0] = state;
+        header[1] = class as u8;
+        header[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+        self.write_bytes(offset, &header)
+    }
+
+    fn zero(&mut self, offset: u64, len: usize) -> Result<()> {
+        let zeros = vec![0u8; CHUNK.min(len)];
+        let mut zeroed = 0;
+        while zeroed < len {
+            let size = CHUNK.min(len - zeroed);
+            self.write_bytes(offset + zeroed as u64, &zeros[..size])?;
+            zeroed += size;
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.vm.try_read_into(to_index(offset)?, buf)?;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, offset: u64, bytes: &[u8]) -> Result<()> {
+        self.vm.try_write_slice(to_index(offset)?, bytes)
+    }
+}
+
+impl<S> Drop for Allocator<S>
+where
+    S: PageStore,
+{
+    fn drop(&mut self) {
+        // virtual memory writes the pages back when it is dropped next
+        let _ = self.write_meta();
+    }
+}
+
+// smallest class whose blocks hold `len` bytes after the header
+fn class_of(len: usize) -> Result<usize> {
+    let too_large = Error::ElementTooLarge {
+        size: len,
+        limit: u32::MAX as usize,
+    };
+    if len > u32::MAX as usize {
+        return Err(too_large);
+    }
+    let size = (len + BLOCK_HEADER).next_power_of_two();
+    Ok((size.trailing_zeros() as usize).max(MIN_CLASS))
+}
+
+fn to_index(offset: u64) -> Result<usize> {
+    usize::try_from(offset).map_err(|_| Error::IndexOutOfRange { index: usize::MAX })
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N]
+        .try_into()
+        .expect("Field out of bounds")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{class_of, Allocator, Handle};
+    use crate::Error;
+    use std::io::Cursor;
+
+    fn blob(seed: usize, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 + seed) as u8).collect()
+    }
+
+    #[test]
+    fn size_classes() {
+        assert_eq!(class_of(0).unwrap(), 4);
+        assert_eq!(class_of(8).unwrap(), 4);
+        assert_eq!(class_of(9).unwrap(), 5);
+        assert_eq!(class_of(4096 - 8).unwrap(), 12);
+    }
+
+    #[test]
+    fn alloc_write_read() {
+        let mut allocator = Allocator::new(Cursor::new(Vec::new()), 128, 3).unwrap();
+        let handles: Vec<Handle> = (0..100)
+            .map(|i| {
+                let handle = allocator.alloc(i * 7).unwrap();
+                allocator.write(handle, 0, &blob(i, i * 7)).unwrap();
+                handle
+            })
+            .collect();
+
+        for (i, &handle) in handles.iter().enumerate() {
+            assert_eq!(allocator.read(handle).unwrap(), blob(i, i * 7));
+        }
+        assert!(matches!(
+            allocator.write(handles[3], 20, &[0; 2]),
+            Err(Error::IndexOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn freed_blocks_are_reused() {
+        let mut allocator = Allocator::new(Cursor::new(Vec::new()), 128, 3).unwrap();
+        let first = allocator.alloc(100).unwrap();
+        allocator.write(first, 0, &blob(1, 100)).unwrap();
+        let second = allocator.alloc(10).unwrap();
+        allocator.free(first).unwrap();
+
+        // same class, the block comes back zeroed
+        let third = allocator.alloc(90).unwrap();
+        assert_eq!(third, first);
+        assert_eq!(allocator.read(third).unwrap(), vec![0; 90]);
+
+        allocator.free(second).unwrap();
+        assert!(matches!(
+            allocator.free(second),
+            Err(Error::InvalidHandle { .. })
+        ));
+        assert!(matches!(
+            allocator.read(Handle::from_raw(3)),
+            Err(Error::InvalidHandle { handle: 3 })
+        ));
+    }
+
+    #[test]
+    fn blocks_are_aligned() {
+        let mut allocator = Allocator::new(Cursor::new(Vec::new()), 128, 3).unwrap();
+        let small = allocator.alloc(4).unwrap();
+        let large = allocator.alloc(1000).unwrap();
+        assert_eq!(large.to_raw() % 1024, 0);
+        // the gap before the large block is handed out again
+        let next = allocator.alloc(20).unwrap();
+        assert!(next.to_raw() > small.to_raw() && next.to_raw() < large.to_raw());
+
+        // a header written inside the blob is no block boundary
+        let mut fake = [0u8; 8];
+        fake[0] = 1;
+        fake[1] = 4;
+        allocator.write(large, 0, &fake).unwrap();
+        let inner = Handle::from_raw(large.to_raw() + 8);
+        assert!(matches!(
+            allocator.free(inner),
+            Err(Error::InvalidHandle { .. })
+        ));
+        assert!(allocator.realloc(inner, 10).is_err());
+    }
+
+    #[test]
+    fn realloc() {
+        let mut allocator = Allocator::new(Cursor::new(Vec::new()), 128, 3).unwrap();
+        let handle = allocator.alloc(20).unwrap();
+        allocator.write(handle, 0, &blob(2, 20)).unwrap();
+
+        // fits in the same block
+        let same = allocator.realloc(handle, 24).unwrap();
+        assert_eq!(same, handle);
+        let mut expected = blob(2, 20);
+        expected.extend_from_slice(&[0; 4]);
+        assert_eq!(allocator.read(same).unwrap(), expected);
+
+        let moved = allocator.realloc(same, 5000).unwrap();
+        assert_ne!(moved, handle);
+        expected.resize(5000, 0);
+        assert_eq!(allocator.read(moved).unwrap(), expected);
+        assert!(allocator.read(handle).is_err());
+
+        let shrunk = allocator.realloc(moved, 3).unwrap();
+        assert_eq!(allocator.read(shrunk).unwrap(), blob(2, 3));
+    }
+
+    #[test]
+    fn reopen() {
+        let mut swap_source = Cursor::new(Vec::new());
+        let (kept, freed) = {
+            let mut allocator = Allocator::new(&mut swap_source, 128, 3).unwrap();
+            let kept = allocator.alloc(300).unwrap();
+            allocator.write(kept, 0, &blob(5, 300)).unwrap();
+            let freed = allocator.alloc(40).unwrap();
+            allocator.free(freed).unwrap();
+            (kept.to_raw(), freed.to_raw())
+        };
+
+        let mut allocator = Allocator::open(&mut swap_source, 3).unwrap();
+        assert_eq!(
+            allocator.read(Handle::from_raw(kept)).unwrap(),
+            blob(5, 300)
+        );
+        // the free list survived too
+        assert_eq!(allocator.alloc(40).unwrap().to_raw(), freed);
+    }
+}
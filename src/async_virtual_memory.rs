@@ -0,0 +1,846 @@
+use crate::checksum::Checksum;
+use crate::encryption::Encryption;
+use crate::error::{Error, Result};
+use crate::header::Header;
+use crate::page::{Page, PageLayout};
+use crate::pod::Pod;
+use crate::policy::{Lru, ReplacementPolicy};
+use crate::virtual_memory::check_buffer_size;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::marker::PhantomData;
+use std::panic;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+
+// Virtual memory paged through tokio's `AsyncRead + AsyncWrite + AsyncSeek`
+// from tasks, in the same format as `VirtualMemory`. Methods take `&self`,
+// so it can be shared between tasks in an `Arc`.
+//
+// A handle to the swap source has one position, so a transfer checks
+// a handle out for its seek and its read or write, and transfers on
+// the same handle take turns. `add_handle` hands in more handles to
+// the same swap source, faults on different pages then overlap up to
+// the number of handles. The page table is never locked across an
+// await: while one task waits for a page to be swapped in, others keep
+// reading and writing pages in the buffer and start their own faults.
+// Faults and flushes run as tasks of their own, one whose future is
+// dropped still finishes.
+//
+// Nothing is written back on drop, there's no way to await a transfer
+// there. Call `flush` before dropping, pages modified since are lost.
+#[derive(Debug)]
+pub struct AsyncVirtualMemory<S, T = u8>
+where
+    S: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send + 'static,
+    T: Pod,
+{
+    inner: Arc<Inner<S>>,
+    values: PhantomData<T>,
+}
+
+// everything a transfer needs, kept alive by faults still running
+#[derive(Debug)]
+struct Inner<S> {
+    // handles to the swap source no transfer is using
+    handles: Mutex<Vec<S>>,
+    // woken whenever a handle goes back to `handles`
+    handle_returned: Notify,
+    state: Mutex<State>,
+    // header writes take turns, so an older header never lands last,
+    // faults never wait on it
+    header_lock: AsyncMutex<()>,
+    // held while a page past `page_count` is written with its holes,
+    // only faults writing there wait on it
+    extending: AsyncMutex<()>,
+    layout: PageLayout,
+    data_offset: u64,
+    // woken whenever a page is swapped in or a fault gives up
+    pages_changed: Notify,
+}
+
+#[derive(Debug)]
+struct State {
+    buffer: Vec<Option<Page>>,
+    page_table: HashMap<usize, Slot>,
+    free_frames: Vec<usize>,
+    policy: Box<dyn ReplacementPolicy>,
+    header: Header,
+    // logical time, see `VirtualMemory::tick`
+    clock: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    // page is in the frame
+    Ready(usize),
+    // page is being swapped in, written out or flushed,
+    // wait for `pages_changed`
+    Busy,
+}
+
+impl State {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+impl<S> AsyncVirtualMemory<S>
+where
+    S: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send + 'static,
+{
+    pub async fn new(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        Self::new_typed(swap_source, page_size, buffer_size).await
+    }
+
+    // reopen swap source created by `new` or `VirtualMemory::new`
+    pub async fn open(swap_source: S, buffer_size: usize) -> Result<Self> {
+        Self::open_typed(swap_source, buffer_size).await
+    }
+}
+
+impl<S, T> AsyncVirtualMemory<S, T>
+where
+    S: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send + 'static,
+    T: Pod,
+{
+    // same as `new`, for values of any `Pod` type
+    pub async fn new_typed(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        let layout = PageLayout::for_values::<T>(page_size, Checksum::None, Encryption::None)?;
+        let vm = Self::with_header(swap_source, Header::new(layout), buffer_size)?;
+        vm.inner.write_header().await?;
+        Ok(vm)
+    }
+
+    // same as `open`, for values of any `Pod` type
+    pub async fn open_typed(mut swap_source: S, buffer_size: usize) -> Result<Self> {
+        if swap_source.seek(SeekFrom::End(0)).await? < Header::SIZE as u64 {
+            return Err(Error::MissingHeader);
+        }
+        let mut bytes = [0u8; Header::SIZE];
+        swap_source.seek(SeekFrom::Start(0)).await?;
+        swap_source.read_exact(&mut bytes).await?;
+        let header = Header::decode(&bytes)?;
+
+        header.check_values::<T>()?;
+        header.check_plain()?;
+        Self::with_header(swap_source, header, buffer_size)
+    }
+
+    fn with_header(swap_source: S, header: Header, buffer_size: usize) -> Result<Self> {
+        check_buffer_size(buffer_size)?;
+
+        let mut policy = Box::new(Lru::new());
+        policy.reset(buffer_size);
+        let state = State {
+            buffer: (0..buffer_size).map(|_| None).collect(),
+            page_table: HashMap::with_capacity(buffer_size),
+            free_frames: (0..buffer_size).rev().collect(),
+            policy,
+            header,
+            clock: 0,
+        };
+
+        let inner = Inner {
+            handles: Mutex::new(vec![swap_source]),
+            handle_returned: Notify::new(),
+            state: Mutex::new(state),
+            header_lock: AsyncMutex::new(()),
+            extending: AsyncMutex::new(()),
+            layout: header.layout,
+            data_offset: header.data_offset,
+            pages_changed: Notify::new(),
+        };
+        Ok(AsyncVirtualMemory {
+            inner: Arc::new(inner),
+            values: PhantomData,
+        })
+    }
+
+    // Hand in another handle to the same swap source with a position of
+    // its own, like the same file opened again. Handles from
+    // `File::try_clone` share one position and can't be used.
+    pub fn add_handle(&self, swap_source: S) {
+        lock(&self.inner.handles).push(swap_source);
+        self.inner.handle_returned.notify_waiters();
+    }
+
+    // one past the highest index ever written
+    pub fn len(&self) -> usize {
+        self.inner.lock().header.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub async fn write(&self, index: usize, value: T) -> Result<()> {
+        let (page_index, value_offset) = self.locate(index);
+        self.with_page(page_index, |state, frame| {
+            page_in(state, frame).set_value(value_offset, value);
+            state.header.len = state.header.len.max(index + 1);
+        })
+        .await
+    }
+
+    pub async fn read(&self, index: usize) -> Result<Option<T>> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+        let (page_index, value_offset) = self.locate(index);
+        self.with_page(page_index, |state, frame| {
+            page_in(state, frame).get_value(value_offset)
+        })
+        .await
+    }
+
+    pub async fn remove(&self, index: usize) -> Result<Option<T>> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+        let (page_index, value_offset) = self.locate(index);
+        self.with_page(page_index, |state, frame| {
+            let page = page_in(state, frame);
+            let value = page.get_value::<T>(value_offset);
+            page.remove_value(value_offset);
+            value
+        })
+        .await
+    }
+
+    // write every modified page and the header to the swap source,
+    // pages stay in the buffer
+    pub async fn flush(&self) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        spawn(async move { inner.flush().await }).await
+    }
+
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let data_size = self.inner.layout.data_size;
+        (index / data_size, index % data_size)
+    }
+
+    // run `f` with the frame of the page, swapping it in first if needed
+    async fn with_page<F, R>(&self, page_index: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut State, usize) -> R,
+    {
+        // validate the address before making room for the page
+        self.inner.page_offset(page_index)?;
+
+        loop {
+            // the guard must be gone before any await
+            let reserved = {
+                let mut state = self.inner.lock();
+                let reserved = match state.page_table.get(&page_index).copied() {
+                    Some(Slot::Ready(frame)) => {
+                        let now = state.tick();
+                        state.policy.access(frame, now);
+                        page_in(&mut state, frame).touch(now);
+                        return Ok(f(&mut state, frame));
+                    }
+                    Some(Slot::Busy) => None,
+                    None => match state.free_frames.pop() {
+                        Some(frame) => Some((frame, None)),
+                        // every frame is being swapped without a victim
+                        None => state.policy.victim().map(|frame| {
+                            let page = state.buffer[frame]
+                                .take()
+                                .expect("Replacement policy chose a free frame");
+                            state.policy.remove(frame);
+                            state.page_table.insert(page.index, Slot::Busy);
+                            (frame, Some(page))
+                        }),
+                    },
+                };
+
+                match reserved {
+                    Some(reserved) => {
+                        state.page_table.insert(page_index, Slot::Busy);
+                        Ok(reserved)
+                    }
+                    // registered under the lock, can't miss the wakeup
+                    None => Err(self.inner.pages_changed.notified()),
+                }
+            };
+            let (frame, evicted) = match reserved {
+                Ok(reserved) => reserved,
+                Err(changed) => {
+                    changed.await;
+                    continue;
+                }
+            };
+
+            let inner = Arc::clone(&self.inner);
+            spawn(async move {
+                let mut fault = Fault {
+                    vm: &inner,
+                    page_index,
+                    frame,
+                    evicted,
+                    done: false,
+                };
+                let page = inner.swap(&mut fault.evicted, page_index).await?;
+                fault.complete(page);
+                Ok(())
+            })
+            .await?;
+
+            // the page is stamped by the fault, not by another access,
+            // unless it was evicted again in the meantime
+            let mut state = self.inner.lock();
+            if state.page_table.get(&page_index) == Some(&Slot::Ready(frame)) {
+                return Ok(f(&mut state, frame));
+            }
+        }
+    }
+}
+
+impl<S> Inner<S> {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+
+    fn page_offset(&self, page_index: usize) -> Result<u64> {
+        page_index
+            .checked_mul(self.layout.page_size)
+            .and_then(|offset| (offset as u64).checked_add(self.data_offset))
+            .ok_or(Error::IndexOutOfRange {
+                index: page_index.saturating_mul(self.layout.data_size),
+            })
+    }
+}
+
+impl<S> Inner<S>
+where
+    S: AsyncRead + AsyncWrite + AsyncSeek + Unpin,
+{
+    // take a handle no transfer is using, waiting for one if needed
+    async fn checkout(&self) -> Checkout<'_, S> {
+        loop {
+            let returned = {
+                let mut handles = lock(&self.handles);
+                match handles.pop() {
+                    Some(swap_source) => {
+                        return Checkout {
+                            vm: self,
+                            swap_source: Some(swap_source),
+                        }
+                    }
+                    // registered under the lock, can't miss the wakeup
+                    None => self.handle_returned.notified(),
+                }
+            };
+            returned.await;
+        }
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut checkout = self.checkout().await;
+        let swap_source = checkout.swap_source();
+        swap_source.seek(SeekFrom::Start(offset)).await?;
+        swap_source.read_exact(buf).await?;
+        Ok(())
+    }
+
+    // written once this returns, other handles read the new bytes
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<()> {
+        let mut checkout = self.checkout().await;
+        let swap_source = checkout.swap_source();
+        swap_source.seek(SeekFrom::Start(offset)).await?;
+        swap_source.write_all(buf).await?;
+        swap_source.flush().await?;
+        Ok(())
+    }
+
+    // write back the evicted page and read the requested one
+    async fn swap(&self, evicted: &mut Option<Page>, page_index: usize) -> Result<Page> {
+        if let Some(page) = evicted.as_mut().filter(|page| page.is_modified) {
+            self.write_page(page).await?;
+        }
+
+        // never written back, whatever the swap source holds there isn't ours
+        let page_count = self.lock().header.page_count;
+        if page_index >= page_count {
+            return Ok(Page::empty(page_index, &self.layout));
+        }
+        let mut bytes = vec![0u8; self.layout.page_size];
+        self.read_at(self.page_offset(page_index)?, &mut bytes)
+            .await?;
+        Page::new(page_index, &self.layout, bytes)
+    }
+
+    async fn write_page(&self, page: &mut Page) -> Result<()> {
+        let offset = self.page_offset(page.index)?;
+        let bytes = page.encode(&self.layout);
+        // writes past the end take turns, so the empty page written for
+        // a hole never lands after the page itself, see `PageLayout::holes`
+        let page_count = self.lock().header.page_count;
+        let _extending = if page.index >= page_count {
+            Some(self.extending.lock().await)
+        } else {
+            None
+        };
+        let page_count = self.lock().header.page_count;
+        for hole in self.layout.holes(page_count, page.index) {
+            let empty = Page::empty(hole, &self.layout).encode(&self.layout);
+            self.write_at(self.page_offset(hole)?, &empty).await?;
+        }
+        self.write_at(offset, &bytes).await?;
+        page.is_modified = false;
+
+        let mut state = self.lock();
+        state.header.page_count = state.header.page_count.max(page.index + 1);
+        Ok(())
+    }
+
+    // modified pages leave the buffer while they are written,
+    // so a fault never writes the same page at the same time
+    async fn flush(&self) -> Result<()> {
+        let mut flush = Flush {
+            vm: self,
+            pages: Vec::new(),
+        };
+        {
+            let mut state = self.lock();
+            for frame in 0..state.buffer.len() {
+                if state.buffer[frame]
+                    .as_ref()
+                    .is_some_and(|page| page.is_modified)
+                {
+                    let page = state.buffer[frame].take().unwrap();
+                    state.policy.remove(frame);
+                    state.page_table.insert(page.index, Slot::Busy);
+                    flush.pages.push((frame, page));
+                }
+            }
+        }
+
+        for (_, page) in &mut flush.pages {
+            self.write_page(page).await?;
+        }
+        drop(flush);
+        self.write_header().await
+    }
+
+    async fn write_header(&self) -> Result<()> {
+        let _header_lock = self.header_lock.lock().await;
+        let header = self.lock().header;
+        self.write_at(0, &header.encode()).await
+    }
+}
+
+// Handle taken out of the pool for one transfer, dropped it goes back
+// whether the transfer finished or not. Every transfer seeks first,
+// where an earlier one left the position doesn't matter.
+struct Checkout<'a, S> {
+    vm: &'a Inner<S>,
+    swap_source: Option<S>,
+}
+
+impl<S> Checkout<'_, S> {
+    fn swap_source(&mut self) -> &mut S {
+        self.swap_source
+            .as_mut()
+            .expect("Handle was already returned")
+    }
+}
+
+impl<S> Drop for Checkout<'_, S> {
+    fn drop(&mut self) {
+        if let Some(swap_source) = self.swap_source.take() {
+            lock(&self.vm.handles).push(swap_source);
+        }
+        self.vm.handle_returned.notify_waiters();
+    }
+}
+
+// Frame reserved for a page being swapped in. Dropped before `complete`,
+// on an error or a panic, it puts everything back.
+struct Fault<'a, S> {
+    vm: &'a Inner<S>,
+    page_index: usize,
+    frame: usize,
+    evicted: Option<Page>,
+    done: bool,
+}
+
+impl<S> Fault<'_, S> {
+    // put the page in its frame, stamped with the time of the fault
+    fn complete(mut self, page: Page) {
+        let mut state = self.vm.lock();
+        if let Some(evicted) = self.evicted.take() {
+            state.page_table.remove(&evicted.index);
+        }
+        let now = state.tick();
+        state.buffer[self.frame] = Some(page);
+        page_in(&mut state, self.frame).touch(now);
+        state
+            .page_table
+            .insert(self.page_index, Slot::Ready(self.frame));
+        state.policy.insert(self.frame, now);
+        self.done = true;
+    }
+}
+
+impl<S> Drop for Fault<'_, S> {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut state = self.vm.lock();
+            state.page_table.remove(&self.page_index);
+            match self.evicted.take() {
+                // evicted page stays, modified unless it was written back
+                Some(page) => put_back(&mut state, self.frame, page),
+                None => state.free_frames.push(self.frame),
+            }
+        }
+        self.vm.pages_changed.notify_waiters();
+    }
+}
+
+// Pages taken out of the buffer by `flush`, dropped they go back
+// to their frames, modified unless they were written.
+struct Flush<'a, S> {
+    vm: &'a Inner<S>,
+    pages: Vec<(usize, Page)>,
+}
+
+impl<S> Drop for Flush<'_, S> {
+    fn drop(&mut self) {
+        let mut state = self.vm.lock();
+        for (frame, page) in self.pages.drain(..) {
+            put_back(&mut state, frame, page);
+        }
+        drop(state);
+        self.vm.pages_changed.notify_waiters();
+    }
+}
+
+fn put_back(state: &mut State, frame: usize, page: Page) {
+    state.page_table.insert(page.index, Slot::Ready(frame));
+    state.policy.insert(frame, page.last_access);
+    state.buffer[frame] = Some(page);
+}
+
+fn page_in(state: &mut State, frame: usize) -> &mut Page {
+    state.buffer[frame]
+        .as_mut()
+        .expect("Failed to find page in buffer")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // no lock is held across an await or a user callback,
+    // a panic can't leave what it guards half updated
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// run `task` as a task of its own, so it finishes even if the future
+// waiting for it is dropped
+async fn spawn<F, R>(task: F) -> Result<R>
+where
+    F: Future<Output = Result<R>> + Send + 'static,
+    R: Send + 'static,
+{
+    match tokio::spawn(task).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(Error::Io(io::Error::other(e))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AsyncVirtualMemory;
+    use crate::{Checksum, Error, Options, VirtualMemory};
+    use std::fs::{self, OpenOptions};
+    use std::future::Future;
+    use std::io::{self, SeekFrom};
+    use std::path::Path;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+    use std::time::Duration;
+    use tempfile::NamedTempFile;
+    use tokio::fs::File;
+    use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+    // the swap file opened again, with a position of its own
+    async fn handle(path: &Path) -> File {
+        tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn tasks_share_memory() {
+        let file = NamedTempFile::new().unwrap();
+        let vm = AsyncVirtualMemory::<_, u32>::new_typed(handle(file.path()).await, 64, 4)
+            .await
+            .unwrap();
+        vm.add_handle(handle(file.path()).await);
+        let vm = Arc::new(vm);
+
+        let tasks: Vec<_> = (0..8)
+            .map(|t| {
+                let vm = Arc::clone(&vm);
+                tokio::spawn(async move {
+                    for i in (t..3000).step_by(8) {
+                        vm.write(i, i as u32 + 1).await.unwrap();
+                    }
+                    for i in (t..3000).step_by(8) {
+                        assert_eq!(vm.read(i).await.unwrap(), Some(i as u32 + 1));
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(vm.remove(7).await.unwrap(), Some(8));
+        vm.flush().await.unwrap();
+        drop(vm);
+
+        // the same format as the blocking virtual memory
+        let swap_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(file.path())
+            .unwrap();
+        let mut vm = VirtualMemory::<_, u32>::open_typed(swap_file, 3).unwrap();
+        assert_eq!(vm.len(), 3000);
+        assert_eq!(vm.read(7), None);
+        assert_eq!(vm.read(2999), Some(3000));
+    }
+
+    #[tokio::test]
+    async fn reopen_after_flush() {
+        let file = NamedTempFile::new().unwrap();
+        let vm = AsyncVirtualMemory::new(handle(file.path()).await, 16, 3)
+            .await
+            .unwrap();
+        for i in 0..100 {
+            vm.write(i, i as u8).await.unwrap();
+        }
+        vm.flush().await.unwrap();
+        drop(vm);
+
+        let vm = AsyncVirtualMemory::open(handle(file.path()).await, 3)
+            .await
+            .unwrap();
+        for i in 0..100 {
+            assert_eq!(vm.read(i).await.unwrap(), Some(i as u8));
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_past_page_count_and_truncated_pages() {
+        let file = NamedTempFile::new().unwrap();
+        let vm = AsyncVirtualMemory::new(handle(file.path()).await, 64, 3)
+            .await
+            .unwrap();
+        vm.write(0, 1).await.unwrap();
+        vm.flush().await.unwrap();
+        drop(vm);
+        let bytes = fs::read(file.path()).unwrap();
+
+        // the only page written back, cut short
+        let truncated = NamedTempFile::new().unwrap();
+        fs::write(truncated.path(), &bytes[..100]).unwrap();
+        let vm = AsyncVirtualMemory::open(handle(truncated.path()).await, 3)
+            .await
+            .unwrap();
+        assert!(matches!(
+            vm.read(0).await,
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+
+        // bytes of a page that was never written back
+        let mut bytes = bytes;
+        bytes.resize(192, 0xff);
+        fs::write(file.path(), &bytes).unwrap();
+        let vm = AsyncVirtualMemory::open(handle(file.path()).await, 3)
+            .await
+            .unwrap();
+        vm.write(56, 2).await.unwrap();
+        assert_eq!(vm.read(57).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn sparse_write_fills_holes() {
+        let file = NamedTempFile::new().unwrap();
+        let options = Options {
+            checksum: Checksum::Crc32c,
+            ..Options::default()
+        };
+        VirtualMemory::<_, u8>::try_new_with_options(file.reopen().unwrap(), 64, 4, options)
+            .unwrap();
+
+        let vm = AsyncVirtualMemory::open(handle(file.path()).await, 4)
+            .await
+            .unwrap();
+        vm.add_handle(handle(file.path()).await);
+        vm.write(10000, 1).await.unwrap();
+        vm.flush().await.unwrap();
+        // every page below the one written passes its checksum
+        assert_eq!(vm.read(100).await.unwrap(), None);
+        drop(vm);
+
+        let mut vm = VirtualMemory::open(file.reopen().unwrap(), 4).unwrap();
+        assert_eq!(vm.try_read(100).unwrap(), None);
+        assert_eq!(vm.try_read(10000).unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn cancelled_fault_finishes() {
+        let file = NamedTempFile::new().unwrap();
+        let vm = AsyncVirtualMemory::new(handle(file.path()).await, 16, 3)
+            .await
+            .unwrap();
+        for i in 0..100 {
+            vm.write(i, i as u8).await.unwrap();
+        }
+
+        // the fault evicts a modified page and goes on without its future,
+        // its task may or may not be done by the first poll
+        let mut read = Box::pin(vm.read(0));
+        let mut cx = Context::from_waker(Waker::noop());
+        let _ = read.as_mut().poll(&mut cx);
+        drop(read);
+
+        for i in 0..100 {
+            assert_eq!(vm.read(i).await.unwrap(), Some(i as u8));
+        }
+    }
+
+    // Once armed, reads wait until every handle is reading. Handles share
+    // the bytes, each has a position of its own.
+    #[derive(Debug)]
+    struct Rendezvous {
+        bytes: Arc<Mutex<Vec<u8>>>,
+        position: u64,
+        gate: Arc<Gate>,
+        arrived: bool,
+    }
+
+    #[derive(Debug, Default)]
+    struct Gate {
+        armed: AtomicBool,
+        // handles that arrived and the wakers of those waiting,
+        // the gate opens once every handle arrived
+        waiting: Mutex<(usize, Vec<Waker>)>,
+        handles: usize,
+    }
+
+    impl AsyncRead for Rendezvous {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if self.gate.armed.load(Ordering::SeqCst) {
+                let gate = Arc::clone(&self.gate);
+                let mut waiting = gate.waiting.lock().unwrap();
+                let (arrived, wakers) = &mut *waiting;
+                if !self.arrived {
+                    self.arrived = true;
+                    *arrived += 1;
+                    wakers.drain(..).for_each(Waker::wake);
+                }
+                if *arrived < gate.handles {
+                    wakers.push(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+
+            let bytes = self.bytes.lock().unwrap();
+            let start = (self.position as usize).min(bytes.len());
+            let n = buf.remaining().min(bytes.len() - start);
+            buf.put_slice(&bytes[start..start + n]);
+            drop(bytes);
+            self.position += n as u64;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Rendezvous {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let start = self.position as usize;
+            let mut bytes = self.bytes.lock().unwrap();
+            if bytes.len() < start + buf.len() {
+                bytes.resize(start + buf.len(), 0);
+            }
+            bytes[start..start + buf.len()].copy_from_slice(buf);
+            drop(bytes);
+            self.position += buf.len() as u64;
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncSeek for Rendezvous {
+        fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+            self.position = match position {
+                SeekFrom::Start(offset) => offset,
+                SeekFrom::End(offset) => (self.bytes.lock().unwrap().len() as i64 + offset) as u64,
+                SeekFrom::Current(offset) => (self.position as i64 + offset) as u64,
+            };
+            Ok(())
+        }
+
+        fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+            Poll::Ready(Ok(self.position))
+        }
+    }
+
+    #[tokio::test]
+    async fn faults_overlap() {
+        let bytes = Arc::new(Mutex::new(Vec::new()));
+        let gate = Arc::new(Gate {
+            handles: 2,
+            ..Gate::default()
+        });
+        let rendezvous = || Rendezvous {
+            bytes: Arc::clone(&bytes),
+            position: 0,
+            gate: Arc::clone(&gate),
+            arrived: false,
+        };
+
+        let vm = AsyncVirtualMemory::new(rendezvous(), 16, 4).await.unwrap();
+        vm.write(0, 1).await.unwrap();
+        vm.write(16, 2).await.unwrap();
+        vm.flush().await.unwrap();
+        drop(vm);
+
+        let vm = AsyncVirtualMemory::open(rendezvous(), 4).await.unwrap();
+        vm.add_handle(rendezvous());
+        gate.armed.store(true, Ordering::SeqCst);
+        let faults = async { tokio::join!(vm.read(0), vm.read(16)) };
+        let (first, second) = tokio::time::timeout(Duration::from_secs(10), faults)
+            .await
+            .expect("page faults didn't overlap");
+        assert_eq!(first.unwrap(), Some(1));
+        assert_eq!(second.unwrap(), Some(2));
+    }
+}
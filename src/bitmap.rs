@@ -81,23 +81,23 @@ mod test {
     fn bitmap_from_u8() {
         let data = vec![1u8; 64];
         let bm = BitMap::from(data.as_ref());
-        assert_eq!(bm.get(0), true);
-        assert_eq!(bm.get(1), false);
-        assert_eq!(bm.get(8), true);
-        assert_eq!(bm.get(9), false);
+        assert!(bm.get(0));
+        assert!(!bm.get(1));
+        assert!(bm.get(8));
+        assert!(!bm.get(9));
     }
 
     #[test]
     fn get_bit() {
         let bm = BitMap::new(64);
-        assert_eq!(bm.get(63), false);
+        assert!(!bm.get(63));
     }
 
     #[test]
     fn set_bit() {
         let mut bm = BitMap::new(64);
         bm.set(8);
-        assert_eq!(bm.get(8), true);
+        assert!(bm.get(8));
     }
 
     #[test]
@@ -105,7 +105,7 @@ mod test {
         let mut bm = BitMap::new(64);
         bm.set(8);
         bm.reset(8);
-        assert_eq!(bm.get(8), false);
+        assert!(!bm.get(8));
     }
 
     #[test]
@@ -113,6 +113,6 @@ mod test {
         let mut bm = BitMap::new(64);
         bm.set(8);
         bm.inverse(8);
-        assert_eq!(bm.get(8), false);
+        assert!(!bm.get(8));
     }
 }
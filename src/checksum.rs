@@ -0,0 +1,200 @@
+// CRC-32C (Castagnoli), reflected polynomial
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32c(bytes: &[u8]) -> u32 {
+    crc32c_append(0, bytes)
+}
+
+// CRC-32C of the bytes `crc` was computed over followed by `bytes`
+pub(crate) fn crc32c_append(crc: u32, bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(!crc, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+// xxHash64 primes
+const P1: u64 = 0x9e37_79b1_85eb_ca87;
+const P2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const P3: u64 = 0x1656_67b1_9e37_79f9;
+const P4: u64 = 0x85eb_ca77_c2b2_ae63;
+const P5: u64 = 0x27d4_eb2f_1656_67c5;
+
+pub(crate) fn xxh64(bytes: &[u8], seed: u64) -> u64 {
+    let round = |acc: u64, lane: u64| {
+        acc.wrapping_add(lane.wrapping_mul(P2))
+            .rotate_left(31)
+            .wrapping_mul(P1)
+    };
+    let u64_at = |bytes: &[u8]| u64::from_le_bytes(bytes[..8].try_into().unwrap());
+
+    let mut rest = bytes;
+    let mut hash = if bytes.len() >= 32 {
+        let mut lanes = [
+            seed.wrapping_add(P1).wrapping_add(P2),
+            seed.wrapping_add(P2),
+            seed,
+            seed.wrapping_sub(P1),
+        ];
+        while rest.len() >= 32 {
+            for (i, lane) in lanes.iter_mut().enumerate() {
+                *lane = round(*lane, u64_at(&rest[i * 8..]));
+            }
+            rest = &rest[32..];
+        }
+
+        let mut hash = lanes[0]
+            .rotate_left(1)
+            .wrapping_add(lanes[1].rotate_left(7))
+            .wrapping_add(lanes[2].rotate_left(12))
+            .wrapping_add(lanes[3].rotate_left(18));
+        for lane in lanes {
+            hash = (hash ^ round(0, lane)).wrapping_mul(P1).wrapping_add(P4);
+        }
+        hash
+    } else {
+        seed.wrapping_add(P5)
+    };
+    hash = hash.wrapping_add(bytes.len() as u64);
+
+    while rest.len() >= 8 {
+        hash ^= round(0, u64_at(rest));
+        hash = hash.rotate_left(27).wrapping_mul(P1).wrapping_add(P4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let lane = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+        hash ^= lane.wrapping_mul(P1);
+        hash = hash.rotate_left(23).wrapping_mul(P2).wrapping_add(P3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash ^= (byte as u64).wrapping_mul(P5);
+        hash = hash.rotate_left(11).wrapping_mul(P1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(P2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(P3);
+    hash ^ (hash >> 32)
+}
+
+// How every page of a swap source is checksummed, chosen when it is created.
+// The checksum covers the page index too, so a page written to the wrong
+// place doesn't pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Checksum {
+    #[default]
+    None,
+    // CRC-32C in the last 4 bytes of every page
+    Crc32c,
+    // xxHash64 in the last 8 bytes of every page
+    XxHash64,
+}
+
+impl Checksum {
+    // header flags of each kind, at most one is set
+    const CRC32C_FLAG: u32 = 1 << 0;
+    const XXHASH64_FLAG: u32 = 1 << 1;
+    pub(crate) const FLAGS: u32 = Self::CRC32C_FLAG | Self::XXHASH64_FLAG;
+
+    // bytes taken at the end of every page
+    pub(crate) fn size(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc32c => 4,
+            Checksum::XxHash64 => 8,
+        }
+    }
+
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc32c => Self::CRC32C_FLAG,
+            Checksum::XxHash64 => Self::XXHASH64_FLAG,
+        }
+    }
+
+    pub(crate) fn from_flags(flags: u32) -> Option<Self> {
+        match flags & Self::FLAGS {
+            0 => Some(Checksum::None),
+            Self::CRC32C_FLAG => Some(Checksum::Crc32c),
+            Self::XXHASH64_FLAG => Some(Checksum::XxHash64),
+            _ => None,
+        }
+    }
+
+    // checksum of page `index` holding `bytes`, `size` bytes little-endian
+    pub(crate) fn compute(self, index: usize, bytes: &[u8]) -> Vec<u8> {
+        let index = index as u64;
+        match self {
+            Checksum::None => Vec::new(),
+            Checksum::Crc32c => crc32c_append(crc32c(&index.to_le_bytes()), bytes)
+                .to_le_bytes()
+                .to_vec(),
+            Checksum::XxHash64 => xxh64(bytes, index).to_le_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32c, crc32c_append, xxh64, Checksum};
+
+    #[test]
+    fn crc32c_check_value() {
+        // standard check value for the ASCII string "123456789"
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn crc32c_append_is_crc32c_of_both() {
+        assert_eq!(crc32c_append(crc32c(b"1234"), b"56789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn xxh64_reference_values() {
+        assert_eq!(xxh64(b"", 0), 0xef46_db37_51d8_e999);
+        assert_eq!(xxh64(b"a", 0), 0xd24e_c4f1_a98c_6e5b);
+        assert_eq!(xxh64(b"abc", 0), 0x44bc_2cf5_ad77_0999);
+        assert_eq!(xxh64(b"abc", 7), 0x9e75_5206_1566_76d7);
+        // long enough for the four lanes, with an 8 byte tail
+        assert_eq!(
+            xxh64(b"0123456789abcdef0123456789abcdef01234567", 0),
+            0x0259_5bf4_5a79_0442
+        );
+    }
+
+    #[test]
+    fn page_index_changes_checksum() {
+        for checksum in [Checksum::Crc32c, Checksum::XxHash64] {
+            let bytes = [1, 2, 3];
+            assert_eq!(checksum.compute(0, &bytes).len(), checksum.size());
+            assert_ne!(checksum.compute(0, &bytes), checksum.compute(1, &bytes));
+            assert_eq!(Checksum::from_flags(checksum.flags()), Some(checksum));
+        }
+        assert_eq!(Checksum::from_flags(Checksum::FLAGS), None);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::{Error, Result};
+
+// How pages are compressed on the way to the swap source, chosen when it
+// is created. Every kind can be named, using one needs its cargo feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    // fast, the `lz4` feature
+    Lz4,
+    // smaller pages, the `zstd` feature
+    Zstd,
+}
+
+impl Compression {
+    // header flags of each kind, at most one is set
+    const LZ4_FLAG: u32 = 1 << 2;
+    const ZSTD_FLAG: u32 = 1 << 3;
+    pub(crate) const FLAGS: u32 = Self::LZ4_FLAG | Self::ZSTD_FLAG;
+
+    #[cfg(feature = "zstd")]
+    const ZSTD_LEVEL: i32 = 3;
+
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => Self::LZ4_FLAG,
+            Compression::Zstd => Self::ZSTD_FLAG,
+        }
+    }
+
+    pub(crate) fn from_flags(flags: u32) -> Option<Self> {
+        match flags & Self::FLAGS {
+            0 => Some(Compression::None),
+            Self::LZ4_FLAG => Some(Compression::Lz4),
+            Self::ZSTD_FLAG => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // fails if the codec wasn't built in
+    pub(crate) fn check_available(self) -> Result<()> {
+        match self {
+            Compression::None => Ok(()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(()),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(()),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::InvalidConfig(
+                "Compression needs its cargo feature, `lz4` or `zstd`",
+            )),
+        }
+    }
+
+    pub(crate) fn compress(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::block::compress(bytes),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(bytes, Self::ZSTD_LEVEL)
+                .expect("Failed to compress a page in memory"),
+            _ => bytes.to_vec(),
+        }
+    }
+
+    // decompress to exactly `out.len()` bytes, page `index` is corrupt otherwise
+    pub(crate) fn decompress(self, index: usize, bytes: &[u8], out: &mut [u8]) -> Result<()> {
+        let len: Option<usize> = match self {
+            Compression::None => (bytes.len() == out.len()).then(|| {
+                out.copy_from_slice(bytes);
+                bytes.len()
+            }),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::block::decompress_into(bytes, out).ok(),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::decompress_to_buffer(bytes, out).ok(),
+            // codec not built in
+            #[allow(unreachable_patterns)]
+            _ => None,
+        };
+        match len {
+            Some(len) if len == out.len() => Ok(()),
+            _ => Err(Error::CorruptPage { index }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Compression;
+
+    #[test]
+    fn flags() {
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            assert_eq!(
+                Compression::from_flags(compression.flags()),
+                Some(compression)
+            );
+        }
+        assert_eq!(Compression::from_flags(Compression::FLAGS), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "lz4", feature = "zstd"))]
+    fn round_trip() {
+        let mut page = vec![0u8; 4096];
+        page[100..110].copy_from_slice(b"0123456789");
+
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let compressed = compression.compress(&page);
+            assert!(compressed.len() < 100);
+
+            let mut out = vec![0xff; 4096];
+            compression.decompress(0, &compressed, &mut out).unwrap();
+            assert_eq!(out, page);
+            // a page of another size doesn't fit
+            assert!(compression
+                .decompress(0, &compressed, &mut vec![0; 4000])
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::page_store::PageStore;
+use crate::virtual_memory::VirtualMemory;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// What reading an unset byte through a cursor does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsetBytes {
+    // unset bytes read as 0
+    Zero,
+    // reading stops before the first unset byte,
+    // or fails with `ErrorKind::InvalidData` when it is the first one
+    Error,
+}
+
+// Stream over the linear address space of virtual memory.
+//
+// The stream ends at `VirtualMemory::len`, writes past the end extend it.
+#[derive(Debug)]
+pub struct MemoryCursor<'a, S>
+where
+    S: PageStore,
+{
+    vm: &'a mut VirtualMemory<S>,
+    position: u64,
+    unset: UnsetBytes,
+}
+
+impl<'a, S> MemoryCursor<'a, S>
+where
+    S: PageStore,
+{
+    pub fn new(vm: &'a mut VirtualMemory<S>, unset: UnsetBytes) -> Self {
+        MemoryCursor {
+            vm,
+            position: 0,
+            unset,
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn index(&self) -> io::Result<usize> {
+        usize::try_from(self.position)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "position out of range"))
+    }
+}
+
+impl<S> Read for MemoryCursor<'_, S>
+where
+    S: PageStore,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let index = self.index()?;
+        let available = self.vm.len().saturating_sub(index);
+        let len = buf.len().min(available);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let buf = &mut buf[..len];
+        let unset = self.vm.try_read_into(index, buf)?;
+        let len = match (self.unset, unset.first()) {
+            (UnsetBytes::Error, Some(first)) if first.start == 0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("byte {} is not set", index),
+                ));
+            }
+            (UnsetBytes::Error, Some(first)) => first.start,
+            _ => len,
+        };
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<S> Write for MemoryCursor<'_, S>
+where
+    S: PageStore,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let index = self.index()?;
+        self.vm.try_write_slice(index, buf)?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    // pages are written back by virtual memory itself
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S> Seek for MemoryCursor<'_, S>
+where
+    S: PageStore,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.vm.len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UnsetBytes;
+    use crate::VirtualMemory;
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use tempfile::tempfile;
+
+    #[test]
+    fn write_seek_read() {
+        let mut vm = VirtualMemory::new(tempfile().unwrap(), 16, 3);
+        let mut cursor = vm.cursor(UnsetBytes::Zero);
+        cursor.write_all(b"hello, virtual world").unwrap();
+        assert_eq!(cursor.position(), 20);
+
+        cursor.seek(SeekFrom::End(-5)).unwrap();
+        let mut word = String::new();
+        cursor.read_to_string(&mut word).unwrap();
+        assert_eq!(word, "world");
+
+        cursor.seek(SeekFrom::Current(-13)).unwrap();
+        let mut buf = [0; 7];
+        cursor.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"virtual");
+
+        assert!(cursor.seek(SeekFrom::Current(-100)).is_err());
+    }
+
+    #[test]
+    fn unset_zero() {
+        let mut vm = VirtualMemory::new(tempfile().unwrap(), 16, 3);
+        vm.write(3, 1);
+        let mut bytes = Vec::new();
+        vm.cursor(UnsetBytes::Zero).read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn unset_error() {
+        let mut vm = VirtualMemory::new(tempfile().unwrap(), 16, 3);
+        vm.write_slice(0, &[1, 2]);
+        vm.write(3, 4);
+
+        let mut cursor = vm.cursor(UnsetBytes::Error);
+        let mut buf = [0; 4];
+        // the first read stops before the hole, the next one fails on it
+        assert_eq!(cursor.read(&mut buf).unwrap(), 2);
+        let error = cursor.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        cursor.seek(SeekFrom::Start(3)).unwrap();
+        assert_eq!(cursor.read(&mut buf).unwrap(), 1);
+        assert_eq!(cursor.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -72,14 +72,15 @@ where
     T: Default + Copy,
 {
     fn from(value: &[T]) -> Self {
-        let in_bytes = value.len() * mem::size_of::<T>();
+        let in_bytes = mem::size_of_val(value);
         if in_bytes > N {
             Self::Heap(Vec::from(value))
         } else {
-            let ptr = value.as_ptr() as *const [T; N];
-            // deref is safe because the size of the `value` is checked
-            // and it will fit the stack-allocated array
-            Self::Inline(value.len(), unsafe { *ptr })
+            // `value` is usually shorter than the array,
+            // so it can't be read as a whole `[T; N]`
+            let mut inline = [T::default(); N];
+            inline[..value.len()].copy_from_slice(value);
+            Self::Inline(value.len(), inline)
         }
     }
 }
@@ -97,6 +98,7 @@ where
 }
 
 #[cfg(test)]
+#[allow(clippy::no_effect)]
 mod test {
     use super::DataLocation;
 
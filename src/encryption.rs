@@ -0,0 +1,302 @@
+// without a cipher built in, `PageCipher` has no values
+#![cfg_attr(
+    not(any(feature = "aes-gcm", feature = "chacha20poly1305")),
+    allow(dead_code, unreachable_code, unused_variables)
+)]
+
+#[cfg(feature = "aes-gcm")]
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+#[cfg(all(feature = "chacha20poly1305", not(feature = "aes-gcm")))]
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+
+use crate::error::{Error, Result};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::{fmt, io};
+
+// How pages are encrypted at rest, chosen when the swap source is
+// created. Every kind can be named, using one needs its cargo feature.
+//
+// The nonce of a sealed page is its index, its write count and a salt
+// drawn at random each time the swap source is created or opened, all
+// three kept with the page. Write counts alone can repeat: after a crash
+// that loses a sealed page, its next write reuses the lost write count.
+// The salt keeps that from reusing a nonce, except with odds of 1 in 2^32
+// for each such page, so keys should still be rotated after crashes.
+// Encrypted swap sources hold at most 2^32 pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encryption {
+    #[default]
+    None,
+    // the `aes-gcm` feature
+    Aes256Gcm,
+    // the `chacha20poly1305` feature
+    ChaCha20Poly1305,
+}
+
+// Source of the 32-byte key of an encrypted swap source, asked once when
+// virtual memory is created or opened. The key is never written to the
+// swap source, a wrong one makes every page fail with `Error::CorruptPage`.
+pub trait KeyProvider {
+    fn key(&self) -> io::Result<[u8; 32]>;
+}
+
+impl KeyProvider for [u8; 32] {
+    fn key(&self) -> io::Result<[u8; 32]> {
+        Ok(*self)
+    }
+}
+
+impl Encryption {
+    // header flags of each kind, at most one is set
+    const AES_256_GCM_FLAG: u32 = 1 << 4;
+    const CHACHA20_POLY1305_FLAG: u32 = 1 << 5;
+    pub(crate) const FLAGS: u32 = Self::AES_256_GCM_FLAG | Self::CHACHA20_POLY1305_FLAG;
+
+    // bytes a sealed page takes on top of the plain one
+    pub(crate) fn overhead(self) -> usize {
+        match self {
+            Encryption::None => 0,
+            _ => PageCipher::OVERHEAD,
+        }
+    }
+
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            Encryption::None => 0,
+            Encryption::Aes256Gcm => Self::AES_256_GCM_FLAG,
+            Encryption::ChaCha20Poly1305 => Self::CHACHA20_POLY1305_FLAG,
+        }
+    }
+
+    pub(crate) fn from_flags(flags: u32) -> Option<Self> {
+        match flags & Self::FLAGS {
+            0 => Some(Encryption::None),
+            Self::AES_256_GCM_FLAG => Some(Encryption::Aes256Gcm),
+            Self::CHACHA20_POLY1305_FLAG => Some(Encryption::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    // cipher with the key from `key`, `None` without encryption
+    pub(crate) fn cipher(self, key: Option<&dyn KeyProvider>) -> Result<Option<PageCipher>> {
+        let key = || match key {
+            Some(key) => Ok(key.key()?),
+            None => Err(Error::InvalidConfig(
+                "Encrypted swap sources need a key provider",
+            )),
+        };
+
+        match self {
+            Encryption::None => Ok(None),
+            #[cfg(feature = "aes-gcm")]
+            Encryption::Aes256Gcm => {
+                let cipher = aes_gcm::Aes256Gcm::new(&key()?.into());
+                Ok(Some(PageCipher::new(Aead::Aes256Gcm(Box::new(cipher)))))
+            }
+            #[cfg(feature = "chacha20poly1305")]
+            Encryption::ChaCha20Poly1305 => {
+                let cipher = chacha20poly1305::ChaCha20Poly1305::new(&key()?.into());
+                Ok(Some(PageCipher::new(Aead::ChaCha20Poly1305(Box::new(
+                    cipher,
+                )))))
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::InvalidConfig(
+                "Encryption needs its cargo feature, `aes-gcm` or `chacha20poly1305`",
+            )),
+        }
+    }
+}
+
+// `Encryption` with its key and the salt of this open. A sealed page is
+// the ciphertext, then the salt, the write count and the tag.
+pub(crate) struct PageCipher {
+    aead: Aead,
+    salt: u32,
+}
+
+enum Aead {
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm(Box<aes_gcm::Aes256Gcm>),
+    #[cfg(feature = "chacha20poly1305")]
+    ChaCha20Poly1305(Box<chacha20poly1305::ChaCha20Poly1305>),
+}
+
+impl PageCipher {
+    const SALT_SIZE: usize = 4;
+    const WRITE_COUNT_SIZE: usize = 4;
+    const TAG_SIZE: usize = 16;
+    const OVERHEAD: usize = Self::SALT_SIZE + Self::WRITE_COUNT_SIZE + Self::TAG_SIZE;
+
+    fn new(aead: Aead) -> Self {
+        PageCipher {
+            aead,
+            salt: random_salt(),
+        }
+    }
+
+    // encrypt `bytes` of page `index` in place and append the trailer
+    pub fn seal(&self, index: usize, write_count: u32, bytes: &mut Vec<u8>) -> Result<()> {
+        let nonce = nonce(index, self.salt, write_count).ok_or(Error::InvalidConfig(
+            "Encrypted swap sources hold at most 2^32 pages",
+        ))?;
+        let tag: [u8; Self::TAG_SIZE] = match self.aead {
+            #[cfg(feature = "aes-gcm")]
+            Aead::Aes256Gcm(ref cipher) => cipher
+                .encrypt_in_place_detached(&nonce.into(), &[], bytes)
+                .expect("Failed to encrypt a page in memory")
+                .into(),
+            #[cfg(feature = "chacha20poly1305")]
+            Aead::ChaCha20Poly1305(ref cipher) => cipher
+                .encrypt_in_place_detached(&nonce.into(), &[], bytes)
+                .expect("Failed to encrypt a page in memory")
+                .into(),
+        };
+        bytes.extend_from_slice(&self.salt.to_le_bytes());
+        bytes.extend_from_slice(&write_count.to_le_bytes());
+        bytes.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    // check the tag and decrypt sealed page `index` in place
+    pub fn open(&self, index: usize, bytes: &mut Vec<u8>) -> Result<()> {
+        let body = bytes
+            .len()
+            .checked_sub(Self::OVERHEAD)
+            .ok_or(Error::CorruptPage { index })?;
+        let salt = u32::from_le_bytes(
+            bytes[body..body + Self::SALT_SIZE]
+                .try_into()
+                .expect("Salt out of bounds"),
+        );
+        let nonce =
+            nonce(index, salt, Self::write_count(bytes)).ok_or(Error::CorruptPage { index })?;
+        let tag: [u8; Self::TAG_SIZE] = bytes[bytes.len() - Self::TAG_SIZE..]
+            .try_into()
+            .expect("Tag out of bounds");
+        bytes.truncate(body);
+
+        let is_authentic: bool = match self.aead {
+            #[cfg(feature = "aes-gcm")]
+            Aead::Aes256Gcm(ref cipher) => cipher
+                .decrypt_in_place_detached(&nonce.into(), &[], bytes, &tag.into())
+                .is_ok(),
+            #[cfg(feature = "chacha20poly1305")]
+            Aead::ChaCha20Poly1305(ref cipher) => cipher
+                .decrypt_in_place_detached(&nonce.into(), &[], bytes, &tag.into())
+                .is_ok(),
+        };
+        if !is_authentic {
+            return Err(Error::CorruptPage { index });
+        }
+        Ok(())
+    }
+
+    // write count in the trailer of a sealed page, read without checking
+    // the tag so a corrupt page is never sealed again with the same nonce
+    pub fn write_count(bytes: &[u8]) -> u32 {
+        match bytes
+            .len()
+            .checked_sub(Self::WRITE_COUNT_SIZE + Self::TAG_SIZE)
+        {
+            Some(start) if bytes.len() >= Self::OVERHEAD => u32::from_le_bytes(
+                bytes[start..start + Self::WRITE_COUNT_SIZE]
+                    .try_into()
+                    .expect("Write count out of bounds"),
+            ),
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Debug for PageCipher {
+    // the key stays out of debug output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageCipher").finish_non_exhaustive()
+    }
+}
+
+// `None` for a page index past the 32 bits of the nonce it gets
+fn nonce(index: usize, salt: u32, write_count: u32) -> Option<[u8; 12]> {
+    let index = u32::try_from(index).ok()?;
+    let mut nonce = [0u8; 12];
+    nonce[..4].copy_from_slice(&index.to_le_bytes());
+    nonce[4..8].copy_from_slice(&salt.to_le_bytes());
+    nonce[8..].copy_from_slice(&write_count.to_le_bytes());
+    Some(nonce)
+}
+
+// std seeds the keys of every `RandomState` from the OS, so hashing the
+// time with a fresh one is random enough for a salt, which isn't secret
+fn random_salt() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(elapsed) = std::time::UNIX_EPOCH.elapsed() {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    hasher.finish() as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::Encryption;
+
+    #[test]
+    fn flags() {
+        for encryption in [
+            Encryption::None,
+            Encryption::Aes256Gcm,
+            Encryption::ChaCha20Poly1305,
+        ] {
+            assert_eq!(Encryption::from_flags(encryption.flags()), Some(encryption));
+        }
+        assert_eq!(Encryption::from_flags(Encryption::FLAGS), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "aes-gcm", feature = "chacha20poly1305"))]
+    fn seal_and_open() {
+        use super::PageCipher;
+        use crate::Error;
+
+        let key = [7u8; 32];
+        for encryption in [Encryption::Aes256Gcm, Encryption::ChaCha20Poly1305] {
+            let cipher = encryption.cipher(Some(&key)).unwrap().unwrap();
+            let page = b"page of plain text".to_vec();
+
+            let mut sealed = page.clone();
+            cipher.seal(3, 9, &mut sealed).unwrap();
+            assert_eq!(sealed.len(), page.len() + encryption.overhead());
+            assert_ne!(&sealed[..page.len()], &page[..]);
+            assert_eq!(PageCipher::write_count(&sealed), 9);
+
+            let mut opened = sealed.clone();
+            cipher.open(3, &mut opened).unwrap();
+            assert_eq!(opened, page);
+
+            // another index, a flipped bit or another key fail the tag
+            assert!(matches!(
+                cipher.open(4, &mut sealed.clone()),
+                Err(Error::CorruptPage { index: 4 })
+            ));
+            let mut flipped = sealed.clone();
+            flipped[0] ^= 1;
+            assert!(cipher.open(3, &mut flipped).is_err());
+            let other = encryption.cipher(Some(&[8u8; 32])).unwrap().unwrap();
+            assert!(other.open(3, &mut sealed.clone()).is_err());
+
+            // the same key after another open seals the same write count
+            // with another nonce, and still opens the earlier page
+            let reopened = encryption.cipher(Some(&key)).unwrap().unwrap();
+            let mut resealed = page.clone();
+            reopened.seal(3, 9, &mut resealed).unwrap();
+            assert_ne!(resealed, sealed);
+            reopened.open(3, &mut sealed.clone()).unwrap();
+
+            assert!(matches!(
+                cipher.seal(usize::MAX, 1, &mut page.clone()),
+                Err(Error::InvalidConfig(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    // failure reported by the swap source
+    Io(io::Error),
+    // swap source is too short to hold a header
+    MissingHeader,
+    // swap source doesn't start with the expected signature
+    BadSignature,
+    // header has the right signature but unusable contents
+    CorruptHeader(&'static str),
+    // header was written by a newer, incompatible format version
+    UnsupportedVersion(u16),
+    // page and buffer sizes that virtual memory can't work with
+    InvalidConfig(&'static str),
+    // index can't be addressed in the swap source
+    IndexOutOfRange {
+        index: usize,
+    },
+    // value at the index was never written or was removed
+    UnsetValue {
+        index: usize,
+    },
+    // page read from the swap source has an inconsistent layout
+    CorruptPage {
+        index: usize,
+    },
+    // handle doesn't point to a live allocation
+    InvalidHandle {
+        handle: u64,
+    },
+    // serialized element doesn't fit in the space reserved for it
+    ElementTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    // element couldn't be serialized or deserialized
+    #[cfg(feature = "serde")]
+    Serialization(bincode::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "swap source I/O error: {}", error),
+            Error::MissingHeader => write!(f, "swap source has no header"),
+            Error::BadSignature => write!(f, "swap source has a bad signature"),
+            Error::CorruptHeader(field) => write!(f, "swap source header has a bad {}", field),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "swap source format version {} is not supported", version)
+            }
+            Error::InvalidConfig(reason) => write!(f, "invalid configuration: {}", reason),
+            Error::IndexOutOfRange { index } => {
+                write!(f, "index {} is out of the addressable range", index)
+            }
+            Error::UnsetValue { index } => write!(f, "value {} is not set", index),
+            Error::CorruptPage { index } => write!(f, "page {} is corrupt", index),
+            Error::InvalidHandle { handle } => {
+                write!(f, "handle {} doesn't point to an allocation", handle)
+            }
+            Error::ElementTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "element of {} bytes doesn't fit in {} bytes",
+                    size, limit
+                )
+            }
+            #[cfg(feature = "serde")]
+            Error::Serialization(error) => write!(f, "serialization error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            #[cfg(feature = "serde")]
+            Error::Serialization(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for Error {
+    fn from(error: bincode::Error) -> Self {
+        Error::Serialization(error)
+    }
+}
+
+// lets `?` surface virtual memory errors from `std::io` trait methods
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(error) => error,
+            error => io::Error::other(error),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use crate::page_store::{write_all_page, PageStore};
+use std::collections::HashMap;
+use std::io;
+
+// What goes wrong with one transfer of a `FaultyStore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // only the first bytes of the page are transferred, the count is returned
+    Short(usize),
+    // nothing is transferred, fails with `ErrorKind::Interrupted`
+    Interrupted,
+    // nothing is transferred, fails as positioning the source failed
+    Seek,
+    // the first bytes of the page are transferred, then it fails
+    Torn(usize),
+}
+
+// Page store that fails on a schedule, to test error handling.
+// Reads and writes are counted separately from 0, a fault scheduled
+// for the nth read hits the nth call to `read_page`.
+#[derive(Debug, Default)]
+pub struct FaultyStore<S> {
+    inner: S,
+    reads: u64,
+    writes: u64,
+    read_faults: HashMap<u64, Fault>,
+    write_faults: HashMap<u64, Fault>,
+    // bytes that can still be written, see `crash_after`
+    crash_budget: Option<u64>,
+    is_crashed: bool,
+}
+
+impl<S> FaultyStore<S>
+where
+    S: PageStore,
+{
+    pub fn new(inner: S) -> Self {
+        FaultyStore {
+            inner,
+            reads: 0,
+            writes: 0,
+            read_faults: HashMap::new(),
+            write_faults: HashMap::new(),
+            crash_budget: None,
+            is_crashed: false,
+        }
+    }
+
+    // fail read number `read`, counting reads done so far
+    pub fn fail_read(&mut self, read: u64, fault: Fault) {
+        self.read_faults.insert(read, fault);
+    }
+
+    // fail write number `write`, counting writes done so far
+    pub fn fail_write(&mut self, write: u64, fault: Fault) {
+        self.write_faults.insert(write, fault);
+    }
+
+    // the process dies once `bytes` more bytes are written: the write
+    // crossing that point is torn there, it and everything after fails
+    pub fn crash_after(&mut self, bytes: u64) {
+        self.crash_budget = Some(bytes);
+    }
+
+    // a write ran past `crash_after`
+    pub fn is_crashed(&self) -> bool {
+        self.is_crashed
+    }
+
+    // calls to `read_page` so far, failed ones included
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    // calls to `write_page` so far, failed ones included
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    // overwrite the first `len` bytes of page `index` with `buf`,
+    // the rest of the page keeps what the inner store has
+    fn write_prefix(&mut self, index: u64, buf: &[u8], len: usize) -> io::Result<usize> {
+        let len = len.min(buf.len());
+        // zeros past the end of the inner store
+        let mut page = vec![0u8; buf.len()];
+        self.inner.read_page(index, &mut page)?;
+        page[..len].copy_from_slice(&buf[..len]);
+        write_all_page(&mut self.inner, index, &page)?;
+        Ok(len)
+    }
+}
+
+impl<S> PageStore for FaultyStore<S>
+where
+    S: PageStore,
+{
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let fault = self.read_faults.remove(&self.reads);
+        self.reads += 1;
+
+        match fault {
+            None => self.inner.read_page(index, buf),
+            Some(Fault::Short(len)) => {
+                let read = self.inner.read_page(index, buf)?;
+                // bytes past a short read are garbage
+                let len = len.min(read);
+                buf[len..].fill(0xa5);
+                Ok(len)
+            }
+            Some(Fault::Interrupted) => Err(interrupted()),
+            Some(Fault::Seek) => Err(seek_failed()),
+            Some(Fault::Torn(len)) => {
+                let read = self.inner.read_page(index, buf)?;
+                buf[len.min(read)..].fill(0xa5);
+                Err(torn())
+            }
+        }
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        let fault = self.write_faults.remove(&self.writes);
+        self.writes += 1;
+
+        if self.is_crashed {
+            return Err(crashed());
+        }
+        if let Some(budget) = self.crash_budget {
+            if buf.len() as u64 > budget {
+                self.is_crashed = true;
+                self.write_prefix(index, buf, budget as usize)?;
+                return Err(crashed());
+            }
+            self.crash_budget = Some(budget - buf.len() as u64);
+        }
+
+        match fault {
+            None => self.inner.write_page(index, buf),
+            Some(Fault::Short(len)) => self.write_prefix(index, buf, len),
+            Some(Fault::Interrupted) => Err(interrupted()),
+            Some(Fault::Seek) => Err(seek_failed()),
+            Some(Fault::Torn(len)) => {
+                self.write_prefix(index, buf, len)?;
+                Err(torn())
+            }
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if self.is_crashed {
+            return Err(crashed());
+        }
+        self.inner.sync()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        self.inner.len()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn interrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "injected interrupt")
+}
+
+fn seek_failed() -> io::Error {
+    io::Error::other("injected seek failure")
+}
+
+fn crashed() -> io::Error {
+    io::Error::other("injected crash")
+}
+
+fn torn() -> io::Error {
+    io::Error::other("injected torn transfer")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Fault, FaultyStore};
+    use crate::page_store::MemoryStore;
+    use crate::{Error, VirtualMemory};
+
+    // 14 values in a page of 16 bytes, the header takes pages 0..4
+    fn filled(store: &mut FaultyStore<MemoryStore>) {
+        let mut vm = VirtualMemory::new(store, 16, 3);
+        for i in 0..100 {
+            vm.write(i, i as u8);
+        }
+        vm.flush().unwrap();
+    }
+
+    fn assert_filled(store: &mut FaultyStore<MemoryStore>) {
+        let mut vm = VirtualMemory::open(store, 3).unwrap();
+        for i in 0..100 {
+            assert_eq!(vm.read(i), Some(i as u8));
+        }
+    }
+
+    #[test]
+    fn short_and_interrupted_writes_are_retried() {
+        let mut store = FaultyStore::new(MemoryStore::new());
+        store.fail_write(1, Fault::Short(3));
+        store.fail_write(2, Fault::Interrupted);
+        store.fail_write(5, Fault::Short(0));
+        filled(&mut store);
+        assert_filled(&mut store);
+    }
+
+    #[test]
+    fn short_and_interrupted_reads_are_retried() {
+        let mut store = FaultyStore::new(MemoryStore::new());
+        filled(&mut store);
+
+        let reads = store.reads();
+        // the header, then the first pages
+        store.fail_read(reads, Fault::Short(10));
+        store.fail_read(reads + 1, Fault::Interrupted);
+        store.fail_read(reads + 3, Fault::Short(1));
+        assert_filled(&mut store);
+    }
+
+    #[test]
+    fn failed_write_keeps_the_page() {
+        let mut store = FaultyStore::new(MemoryStore::new());
+        {
+            let mut vm = VirtualMemory::new(&mut store, 16, 3);
+            for i in 0..42 {
+                vm.write(i, i as u8);
+            }
+            let writes = vm.swap_source.writes();
+            vm.swap_source.fail_write(writes, Fault::Seek);
+            vm.swap_source.fail_write(writes + 1, Fault::Torn(7));
+
+            // loading a fourth page evicts page 0, which can't be written
+            assert!(matches!(vm.try_write(42, 42), Err(Error::Io(_))));
+            assert!(matches!(vm.try_write(42, 42), Err(Error::Io(_))));
+            // nothing was lost, the page is written again
+            vm.write(42, 42);
+            for i in 43..100 {
+                vm.write(i, i as u8);
+            }
+        }
+        assert_filled(&mut store);
+    }
+
+    #[test]
+    fn failed_read_leaves_no_page() {
+        let mut store = FaultyStore::new(MemoryStore::new());
+        filled(&mut store);
+
+        let mut vm = VirtualMemory::open(&mut store, 3).unwrap();
+        let reads = vm.swap_source.reads();
+        vm.swap_source.fail_read(reads, Fault::Seek);
+        vm.swap_source.fail_read(reads + 1, Fault::Torn(5));
+        assert!(matches!(vm.try_read(0), Err(Error::Io(_))));
+        assert!(matches!(vm.try_read(0), Err(Error::Io(_))));
+        assert_eq!(vm.read(0), Some(0));
+    }
+
+    #[test]
+    fn endless_short_writes_fail() {
+        let mut store = FaultyStore::new(MemoryStore::new());
+        for write in 0..100 {
+            store.fail_write(write, Fault::Short(1));
+        }
+        assert!(matches!(
+            VirtualMemory::try_new(&mut store, 16, 3),
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::checksum::{crc32c, Checksum};
+use crate::compression::Compression;
+use crate::div_ceil;
+use crate::encryption::Encryption;
+use crate::error::{Error, Result};
+use crate::page::PageLayout;
+use crate::pod::Pod;
+use std::mem;
+
+const MAGIC: &[u8; 2] = b"VM";
+// bump when the on-disk layout changes incompatibly
+const FORMAT_VERSION: u16 = 1;
+// flags this version understands, files with other bits set are rejected
+const KNOWN_FLAGS: u32 = Checksum::FLAGS | Compression::FLAGS | Encryption::FLAGS;
+
+// On-disk header, stored little-endian at the start of the swap source:
+//
+//  0..2   magic "VM"
+//  2..4   format version
+//  4..8   flags, the page checksum kind in bits 0..2, the
+//         compression kind in bits 2..4, the encryption in bits 4..6
+//  8..16  page size
+// 16..24  offset of the first page
+// 24..32  page count, pages ever written back
+// 32..40  length, one past the highest written index
+// 40..44  bitmap size of a page
+// 44..48  data size of a page, in values
+// 48..52  value size
+// 52..60  length kept by a container built on the swap source,
+//         the values of a `VVec`, zero otherwise
+// 60..64  CRC-32C of bytes 0..60
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub version: u16,
+    pub flags: u32,
+    pub layout: PageLayout,
+    pub data_offset: u64,
+    pub page_count: usize,
+    pub len: usize,
+    pub container_len: usize,
+}
+
+impl Header {
+    pub const SIZE: usize = 64;
+    const CHECKSUM_OFFSET: usize = Self::SIZE - 4;
+
+    pub fn new(layout: PageLayout) -> Self {
+        let page_size = layout.page_size;
+        // pages start on a page boundary after the header
+        let data_offset = (div_ceil(Self::SIZE, page_size) * page_size) as u64;
+
+        Header {
+            version: FORMAT_VERSION,
+            flags: layout.checksum.flags() | layout.encryption.flags(),
+            layout,
+            data_offset,
+            page_count: 0,
+            len: 0,
+            container_len: 0,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..2].copy_from_slice(MAGIC);
+        bytes[2..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[8..16].copy_from_slice(&(self.layout.page_size as u64).to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.data_offset.to_le_bytes());
+        bytes[24..32].copy_from_slice(&(self.page_count as u64).to_le_bytes());
+        bytes[32..40].copy_from_slice(&(self.len as u64).to_le_bytes());
+        bytes[40..44].copy_from_slice(&(self.layout.bitmap_size as u32).to_le_bytes());
+        bytes[44..48].copy_from_slice(&(self.layout.data_size as u32).to_le_bytes());
+        bytes[48..52].copy_from_slice(&(self.layout.element_size as u32).to_le_bytes());
+        bytes[52..60].copy_from_slice(&(self.container_len as u64).to_le_bytes());
+
+        let checksum = crc32c(&bytes[..Self::CHECKSUM_OFFSET]);
+        bytes[Self::CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self> {
+        if &bytes[0..2] != MAGIC {
+            return Err(Error::BadSignature);
+        }
+
+        let checksum = u32::from_le_bytes(read(bytes, Self::CHECKSUM_OFFSET));
+        if checksum != crc32c(&bytes[..Self::CHECKSUM_OFFSET]) {
+            return Err(Error::CorruptHeader("checksum"));
+        }
+
+        let version = u16::from_le_bytes(read(bytes, 2));
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let flags = u32::from_le_bytes(read(bytes, 4));
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(Error::CorruptHeader("flags"));
+        }
+        let checksum = Checksum::from_flags(flags).ok_or(Error::CorruptHeader("flags"))?;
+        Compression::from_flags(flags).ok_or(Error::CorruptHeader("flags"))?;
+        let encryption = Encryption::from_flags(flags).ok_or(Error::CorruptHeader("flags"))?;
+
+        let page_size = to_usize(u64::from_le_bytes(read(bytes, 8)), "page size")?;
+        if page_size <= 1 {
+            return Err(Error::CorruptHeader("page size"));
+        }
+
+        let element_size = u32::from_le_bytes(read(bytes, 48)) as usize;
+        if element_size == 0 {
+            return Err(Error::CorruptHeader("value size"));
+        }
+
+        let layout = PageLayout::with_encryption(page_size, element_size, checksum, encryption);
+        let data_offset = u64::from_le_bytes(read(bytes, 16));
+        // pages are addressed by index, so they start on a page boundary
+        if data_offset < Self::SIZE as u64 || data_offset % page_size as u64 != 0 {
+            return Err(Error::CorruptHeader("data offset"));
+        }
+
+        let bitmap_size = u32::from_le_bytes(read(bytes, 40)) as usize;
+        let data_size = u32::from_le_bytes(read(bytes, 44)) as usize;
+        if bitmap_size != layout.bitmap_size || data_size != layout.data_size {
+            return Err(Error::CorruptHeader("page layout"));
+        }
+
+        Ok(Header {
+            version,
+            flags,
+            layout,
+            data_offset,
+            page_count: to_usize(u64::from_le_bytes(read(bytes, 24)), "page count")?,
+            len: to_usize(u64::from_le_bytes(read(bytes, 32)), "length")?,
+            container_len: to_usize(u64::from_le_bytes(read(bytes, 52)), "container length")?,
+        })
+    }
+
+    // the swap source holds values of type `T`
+    pub fn check_values<T: Pod>(&self) -> Result<()> {
+        if self.layout.element_size != mem::size_of::<T>() {
+            return Err(Error::InvalidConfig(
+                "Value size doesn't match the swap source",
+            ));
+        }
+        Ok(())
+    }
+
+    // pages are neither compressed nor encrypted, for virtual memory
+    // other than `VirtualMemory` that reads them as they are
+    pub fn check_plain(&self) -> Result<()> {
+        if self.compression() != Compression::None {
+            return Err(Error::InvalidConfig(
+                "Compressed swap sources can only be opened by VirtualMemory",
+            ));
+        }
+        if self.layout.encryption != Encryption::None {
+            return Err(Error::InvalidConfig(
+                "Encrypted swap sources can only be opened by VirtualMemory",
+            ));
+        }
+        Ok(())
+    }
+
+    // pages are found through a `SlotTable` unless this is `None`
+    pub fn compression(&self) -> Compression {
+        Compression::from_flags(self.flags).unwrap_or_default()
+    }
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N]
+        .try_into()
+        .expect("Header field out of bounds")
+}
+
+fn to_usize(value: u64, field: &'static str) -> Result<usize> {
+    usize::try_from(value).map_err(|_| Error::CorruptHeader(field))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Header;
+    use crate::checksum::Checksum;
+    use crate::compression::Compression;
+    use crate::page::PageLayout;
+    use crate::Error;
+
+    #[test]
+    fn layout() {
+        // 64 bytes of header rounded up to whole pages
+        assert_eq!(Header::new(PageLayout::new(9, 1)).data_offset, 72);
+        assert_eq!(Header::new(PageLayout::new(4096, 8)).data_offset, 4096);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut header = Header::new(PageLayout::new(16, 1));
+        header.page_count = 7;
+        header.len = 98;
+        header.container_len = 90;
+        assert_eq!(Header::decode(&header.encode()).unwrap(), header);
+    }
+
+    #[test]
+    fn typed_layout() {
+        let header = Header::new(PageLayout::new(64, 4));
+        let decoded = Header::decode(&header.encode()).unwrap();
+        assert_eq!(decoded.layout.element_size, 4);
+        assert_eq!(decoded.layout.data_size, 15);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut bytes = Header::new(PageLayout::new(16, 1)).encode();
+        bytes[8] ^= 1;
+        assert!(matches!(
+            Header::decode(&bytes),
+            Err(Error::CorruptHeader("checksum"))
+        ));
+    }
+
+    #[test]
+    fn newer_version() {
+        let mut header = Header::new(PageLayout::new(16, 1));
+        header.version += 1;
+        assert!(matches!(
+            Header::decode(&header.encode()),
+            Err(Error::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn unknown_flags() {
+        let mut header = Header::new(PageLayout::new(16, 1));
+        header.flags = 1 << 31;
+        assert!(matches!(
+            Header::decode(&header.encode()),
+            Err(Error::CorruptHeader("flags"))
+        ));
+    }
+
+    #[test]
+    fn misaligned_data_offset() {
+        let mut header = Header::new(PageLayout::new(16, 1));
+        header.data_offset = 72;
+        assert!(matches!(
+            Header::decode(&header.encode()),
+            Err(Error::CorruptHeader("data offset"))
+        ));
+    }
+
+    #[test]
+    fn checksum_kind() {
+        let header = Header::new(PageLayout::with_checksum(64, 1, Checksum::XxHash64));
+        let decoded = Header::decode(&header.encode()).unwrap();
+        assert_eq!(decoded.layout.checksum, Checksum::XxHash64);
+        assert_eq!(decoded, header);
+
+        let mut header = header;
+        header.flags = Checksum::FLAGS;
+        assert!(matches!(
+            Header::decode(&header.encode()),
+            Err(Error::CorruptHeader("flags"))
+        ));
+    }
+
+    #[test]
+    fn compression_kind() {
+        let mut header = Header::new(PageLayout::new(64, 1));
+        header.flags |= Compression::Zstd.flags();
+        let decoded = Header::decode(&header.encode()).unwrap();
+        assert_eq!(decoded.compression(), Compression::Zstd);
+
+        header.flags |= Compression::FLAGS;
+        assert!(matches!(
+            Header::decode(&header.encode()),
+            Err(Error::CorruptHeader("flags"))
+        ));
+    }
+}
@@ -1,13 +1,65 @@
+mod allocator;
+#[cfg(feature = "tokio")]
+mod async_virtual_memory;
 mod bitmap;
+mod checksum;
+mod compression;
+mod cursor;
 mod data_location;
+mod encryption;
+mod error;
+mod faulty_store;
+mod header;
+#[cfg(feature = "mmap")]
+mod mmap_file;
+mod options;
 mod page;
+mod page_store;
+mod pod;
+pub mod policy;
+mod shared_virtual_memory;
+mod slot_table;
+mod stats;
+#[cfg(feature = "serde")]
+mod varray;
+#[cfg(feature = "serde")]
+mod vbtreemap;
+#[cfg(feature = "serde")]
+mod vhashmap;
 mod virtual_memory;
+mod vvec;
+mod wal_store;
 
+pub use allocator::{Allocator, Handle};
+#[cfg(feature = "tokio")]
+pub use async_virtual_memory::AsyncVirtualMemory;
+pub use checksum::Checksum;
+pub use compression::Compression;
+pub use cursor::{MemoryCursor, UnsetBytes};
+pub use encryption::{Encryption, KeyProvider};
+pub use error::{Error, Result};
+pub use faulty_store::{Fault, FaultyStore};
+#[cfg(feature = "mmap")]
+pub use mmap_file::MmapFile;
+pub use options::Options;
+pub use page_store::{MemoryStore, PageStore, RwsStore, SharedPageStore};
+pub use pod::Pod;
+pub use policy::ReplacementPolicy;
+pub use shared_virtual_memory::SharedVirtualMemory;
+pub use stats::Stats;
+#[cfg(feature = "serde")]
+pub use varray::VArray;
+#[cfg(feature = "serde")]
+pub use vbtreemap::VBTreeMap;
+#[cfg(feature = "serde")]
+pub use vhashmap::VHashMap;
 pub use virtual_memory::VirtualMemory;
+pub use vvec::VVec;
+pub use wal_store::WalStore;
 
 pub(crate) const BITS_IN_BYTE: usize = 8;
 
 // round up `dividend` to the nearest usize value of `divisor`
 pub(crate) fn div_ceil(dividend: usize, divisor: usize) -> usize {
-    (dividend + divisor - 1) / divisor
+    dividend.div_ceil(divisor)
 }
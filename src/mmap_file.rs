@@ -0,0 +1,336 @@
+use crate::page_store::{page_offset, PageStore};
+use memmap2::{MmapMut, MmapOptions};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// windows start on this boundary, a multiple of the page size
+// and of the allocation granularity of every supported platform
+const GRANULARITY: usize = 64 * 1024;
+const DEFAULT_WINDOW_SIZE: usize = 16 * GRANULARITY;
+// modified windows left behind that wait for `flush`,
+// past that they are `msync`ed when one more is left behind
+const RETIRED_WINDOWS: usize = 16;
+
+// Swap file accessed through a memory mapping instead of `read` and
+// `write` calls, a `PageStore` for `VirtualMemory<MmapFile>`. One window
+// of the file is mapped at a time and moved when a transfer falls
+// outside of it. Pages are copied between the mapping and the buffer of
+// `VirtualMemory` like with any other store, what the mapping saves is
+// the system call of every transfer.
+//
+// `flush` is `msync` of every window modified since the last one,
+// windows left behind stay mapped until then.
+//
+// The file must not be resized or modified by anyone else while mapped.
+#[derive(Debug)]
+pub struct MmapFile {
+    file: File,
+    // bytes written so far, the file itself grows in whole windows
+    len: u64,
+    position: u64,
+    window_size: usize,
+    window: Option<Window>,
+    // modified windows that were moved away from, not `msync`ed yet
+    retired: Vec<MmapMut>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: u64,
+    map: MmapMut,
+    is_modified: bool,
+}
+
+impl MmapFile {
+    // `file` must be open for reading and writing
+    pub fn new(file: File) -> io::Result<Self> {
+        Self::with_window_size(file, DEFAULT_WINDOW_SIZE)
+    }
+
+    // map `window_size` bytes at a time, rounded up to 64 KiB
+    pub fn with_window_size(file: File, window_size: usize) -> io::Result<Self> {
+        let window_size = window_size.max(1).div_ceil(GRANULARITY) * GRANULARITY;
+        let len = file.metadata()?.len();
+        Ok(MmapFile {
+            file,
+            len,
+            position: 0,
+            window_size,
+            window: None,
+            retired: Vec::new(),
+        })
+    }
+
+    // unmap the file and trim it to the bytes written
+    pub fn into_inner(mut self) -> io::Result<File> {
+        self.close()?;
+        let file = self.file.try_clone()?;
+        Ok(file)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.msync()?;
+        self.window = None;
+        if self.file.metadata()?.len() > self.len {
+            self.file.set_len(self.len)?;
+        }
+        Ok(())
+    }
+
+    // `msync` of every window modified since the last call
+    fn msync(&mut self) -> io::Result<()> {
+        while let Some(map) = self.retired.last() {
+            map.flush()?;
+            self.retired.pop();
+        }
+        if let Some(window) = &mut self.window {
+            if window.is_modified {
+                window.map.flush()?;
+                window.is_modified = false;
+            }
+        }
+        Ok(())
+    }
+
+    // window holding `position`, mapped for bytes up to `end` at least
+    fn window(&mut self, position: u64, end: u64) -> io::Result<&mut Window> {
+        let window_size = self.window_size as u64;
+        let start = position / window_size * window_size;
+
+        let mapped = match &self.window {
+            Some(window) => window.start == start && window.start + window.map.len() as u64 >= end,
+            None => false,
+        };
+        if !mapped {
+            if let Some(window) = self.window.take() {
+                if window.is_modified {
+                    if self.retired.len() == RETIRED_WINDOWS {
+                        self.msync()?;
+                    }
+                    // written out in the background, `flush` waits for it
+                    window.map.flush_async()?;
+                    self.retired.push(window.map);
+                }
+            }
+
+            let file_len = self.file.metadata()?.len();
+            if file_len < end {
+                // grow by whole windows so writes in the window don't remap
+                self.file.set_len(start + window_size)?;
+            }
+            let len = self.file.metadata()?.len().min(start + window_size) - start;
+
+            // the mapping is private to this value and the file is not
+            // changed by anyone else, see the type comment
+            let map = unsafe {
+                MmapOptions::new()
+                    .offset(start)
+                    .len(len as usize)
+                    .map_mut(&self.file)?
+            };
+            self.window = Some(Window {
+                start,
+                map,
+                is_modified: false,
+            });
+        }
+        Ok(self.window.as_mut().expect("Window was just mapped"))
+    }
+
+    // end of the window holding `position`
+    fn window_end(&self, position: u64) -> u64 {
+        (position / self.window_size as u64 + 1) * self.window_size as u64
+    }
+
+    // copy bytes at `position` to `buf` up to the end of the window
+    // or of the file, returns bytes copied
+    fn copy_out(&mut self, position: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let end = position
+            .saturating_add(buf.len() as u64)
+            .min(self.len)
+            .min(self.window_end(position));
+        let window = self.window(position, end)?;
+
+        let from = (position - window.start) as usize;
+        let to = (end - window.start) as usize;
+        buf[..to - from].copy_from_slice(&window.map[from..to]);
+        Ok(to - from)
+    }
+
+    // copy `buf` to bytes at `position` up to the end of the window,
+    // returns bytes copied
+    fn copy_in(&mut self, position: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let end = position
+            .checked_add(buf.len() as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "write past u64::MAX"))?
+            .min(self.window_end(position));
+        let window = self.window(position, end)?;
+
+        let from = (position - window.start) as usize;
+        let to = (end - window.start) as usize;
+        window.map[from..to].copy_from_slice(&buf[..to - from]);
+        window.is_modified = true;
+        self.len = self.len.max(end);
+        Ok(to - from)
+    }
+}
+
+impl Read for MmapFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.copy_out(self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for MmapFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.copy_in(self.position, buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    // msync of every modified window
+    fn flush(&mut self) -> io::Result<()> {
+        self.msync()
+    }
+}
+
+impl Seek for MmapFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+// pages are copied straight between the mapping and `buf`, the position
+// is left alone, `sync` is `msync` of every modified window followed by
+// `fsync` of the file
+impl PageStore for MmapFile {
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = page_offset(index, buf.len())?;
+        let mut read = 0;
+        while read < buf.len() {
+            match self.copy_out(offset + read as u64, &mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        let offset = page_offset(index, buf.len())?;
+        let mut written = 0;
+        while written < buf.len() {
+            written += self.copy_in(offset + written as u64, &buf[written..])?;
+        }
+        Ok(written)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.msync()?;
+        self.file.sync_all()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.msync()
+    }
+}
+
+impl Drop for MmapFile {
+    fn drop(&mut self) {
+        // errors can't be reported from drop, use `into_inner` to see them
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MmapFile;
+    use crate::VirtualMemory;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn read_write_seek() {
+        let mut file = MmapFile::with_window_size(tempfile::tempfile().unwrap(), 1).unwrap();
+        // crosses from the first window to the second one
+        let bytes: Vec<u8> = (0..100_000).map(|i| (i % 253) as u8).collect();
+        file.write_all(&bytes).unwrap();
+        file.flush().unwrap();
+
+        let mut read = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut read).unwrap();
+        assert_eq!(read, bytes);
+
+        assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 99_990);
+        let mut tail = [0u8; 20];
+        assert_eq!(file.read(&mut tail).unwrap(), 10);
+
+        // trimmed to what was written, not to whole windows
+        let file = file.into_inner().unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn flush_covers_windows_left_behind() {
+        use crate::PageStore;
+
+        let mut file = MmapFile::with_window_size(tempfile::tempfile().unwrap(), 1).unwrap();
+        for window in 0..3 {
+            file.write_page(window * 16, &[1; 4096]).unwrap();
+        }
+        // a window that was only read isn't waiting for `flush`
+        let mut page = [0u8; 4096];
+        file.read_page(0, &mut page).unwrap();
+        file.read_page(16, &mut page).unwrap();
+        assert_eq!(file.retired.len(), 3);
+
+        PageStore::flush(&mut file).unwrap();
+        assert!(file.retired.is_empty());
+        assert!(!file.window.as_ref().unwrap().is_modified);
+    }
+
+    #[test]
+    fn virtual_memory_over_mmap() {
+        let swap_file = tempfile::tempfile().unwrap();
+        let mmap = MmapFile::with_window_size(swap_file.try_clone().unwrap(), 1).unwrap();
+        {
+            let mut vm = VirtualMemory::<_, u64>::try_new_typed(mmap, 4096, 4).unwrap();
+            for i in 0..50_000 {
+                vm.write(i, i as u64 * 5);
+            }
+            vm.sync().unwrap();
+        }
+
+        // the same file read back without a mapping
+        let mut vm = VirtualMemory::<_, u64>::open_typed(swap_file, 4).unwrap();
+        assert_eq!(vm.len(), 50_000);
+        for i in (0..50_000).step_by(7) {
+            assert_eq!(vm.read(i), Some(i as u64 * 5));
+        }
+    }
+}
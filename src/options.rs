@@ -0,0 +1,13 @@
+use crate::checksum::Checksum;
+use crate::compression::Compression;
+use crate::encryption::Encryption;
+
+// Format of a new swap source, kept in its header so `open` finds it again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    pub checksum: Checksum,
+    // needs the `lz4` or `zstd` feature to be used
+    pub compression: Compression,
+    // needs the `aes-gcm` or `chacha20poly1305` feature and a `KeyProvider`
+    pub encryption: Encryption,
+}
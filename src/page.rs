@@ -1,94 +1,362 @@
 use crate::bitmap::BitMap;
+use crate::checksum::Checksum;
+use crate::encryption::Encryption;
+use crate::error::{Error, Result};
+use crate::pod::{self, Pod};
 use crate::{div_ceil, BITS_IN_BYTE};
-use std::time::SystemTime;
+use std::mem;
+use std::ops::Range;
+
+// How values of one size are laid out in a page:
+// bitmap with a bit per value first, then the values,
+// then the checksum if there is one, all sealed by the encryption if any
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PageLayout {
+    pub page_size: usize,
+    pub element_size: usize,
+    // values in a page
+    pub data_size: usize,
+    pub bitmap_size: usize,
+    pub checksum: Checksum,
+    pub encryption: Encryption,
+}
+
+impl PageLayout {
+    #[cfg(test)]
+    pub fn new(page_size: usize, element_size: usize) -> Self {
+        Self::with_checksum(page_size, element_size, Checksum::None)
+    }
+
+    #[cfg(test)]
+    pub fn with_checksum(page_size: usize, element_size: usize, checksum: Checksum) -> Self {
+        Self::with_encryption(page_size, element_size, checksum, Encryption::None)
+    }
+
+    pub fn with_encryption(
+        page_size: usize,
+        element_size: usize,
+        checksum: Checksum,
+        encryption: Encryption,
+    ) -> Self {
+        // every value takes `element_size` bytes and a bit,
+        // for bytes the data section is 8/9 of the page and 1/9 is bitmap
+        let usable = page_size.saturating_sub(checksum.size() + encryption.overhead());
+        let data_size = usable * BITS_IN_BYTE / (element_size * BITS_IN_BYTE + 1);
+        let bitmap_size = div_ceil(data_size, BITS_IN_BYTE);
+
+        PageLayout {
+            page_size,
+            element_size,
+            data_size,
+            bitmap_size,
+            checksum,
+            encryption,
+        }
+    }
+
+    // layout of `T` values in pages of `page_size`,
+    // checked to be usable by every kind of virtual memory
+    pub fn for_values<T: Pod>(
+        page_size: usize,
+        checksum: Checksum,
+        encryption: Encryption,
+    ) -> Result<Self> {
+        if page_size <= 1 {
+            return Err(Error::InvalidConfig(
+                "Virtual memory should have page size > 1",
+            ));
+        }
+        if mem::size_of::<T>() == 0 {
+            return Err(Error::InvalidConfig(
+                "Virtual memory can't store zero-sized values",
+            ));
+        }
+
+        let layout = Self::with_encryption(page_size, mem::size_of::<T>(), checksum, encryption);
+        if layout.data_size == 0 {
+            return Err(Error::InvalidConfig(
+                "Virtual memory page should hold at least one value",
+            ));
+        }
+        Ok(layout)
+    }
+
+    // bytes of a page before it is sealed
+    pub fn plain_size(&self) -> usize {
+        self.page_size - self.encryption.overhead()
+    }
+
+    // Pages skipped by a write of `page_index` past `page_count`. Below
+    // `page_count` a zeroed page fails its checksum or doesn't open, so
+    // the holes are written as empty pages before it passes them.
+    // Zeroed plain pages read as empty, there's nothing to write.
+    pub fn holes(&self, page_count: usize, page_index: usize) -> Range<usize> {
+        if self.checksum == Checksum::None && self.encryption == Encryption::None {
+            return 0..0;
+        }
+        page_count..page_index
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct Page {
     pub index: usize,
     pub is_modified: bool,
-    pub last_access: SystemTime,
+    // logical time of the last access, see `VirtualMemory::tick`
+    pub last_access: u64,
+    // times the page was sealed, part of its nonce with `Encryption`
+    pub write_count: u32,
+    element_size: usize,
     pub bitmap: BitMap,
     pub values: Vec<u8>,
 }
 
 impl Page {
-    pub fn new(index: usize, size: usize, data: Vec<u8>) -> Self {
-        let data_size = size * BITS_IN_BYTE / 9;
-        let bitmap_size = div_ceil(data_size, BITS_IN_BYTE);
-        let (bitmap, values) = data.split_at(bitmap_size);
+    pub fn new(index: usize, layout: &PageLayout, data: Vec<u8>) -> Result<Self> {
+        let (body, checksum) = data.split_at(data.len() - layout.checksum.size());
+        // zeros are no exception, a page that was never written is `empty`
+        if checksum != layout.checksum.compute(index, body) {
+            return Err(Error::CorruptPage { index });
+        }
 
-        Page {
+        let (bitmap, values) = body.split_at(layout.bitmap_size);
+        let bitmap = BitMap::from(bitmap);
+
+        // bits past the data section are never set by a valid page
+        if (layout.data_size..layout.bitmap_size * BITS_IN_BYTE).any(|i| bitmap.get(i)) {
+            return Err(Error::CorruptPage { index });
+        }
+
+        Ok(Page {
             index,
             is_modified: false,
-            last_access: SystemTime::now(),
-            bitmap: BitMap::from(bitmap),
+            last_access: 0,
+            write_count: 0,
+            element_size: layout.element_size,
+            bitmap,
             values: Vec::from(values),
+        })
+    }
+
+    // page that was never written, no value is set
+    pub fn empty(index: usize, layout: &PageLayout) -> Self {
+        let values = layout.plain_size() - layout.checksum.size() - layout.bitmap_size;
+        Page {
+            index,
+            is_modified: false,
+            last_access: 0,
+            write_count: 0,
+            element_size: layout.element_size,
+            bitmap: BitMap::from(&vec![0u8; layout.bitmap_size][..]),
+            values: vec![0; values],
         }
     }
 
-    pub fn set_value(&mut self, index: usize, value: u8) {
+    // bytes of the page in the swap source, checksum included
+    pub fn encode(&self, layout: &PageLayout) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(layout.page_size);
+        bytes.extend_from_slice(self.bitmap.as_ref());
+        bytes.extend_from_slice(&self.values);
+        let checksum = layout.checksum.compute(self.index, &bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    pub fn touch(&mut self, now: u64) {
+        self.last_access = now;
+    }
+
+    pub fn set_value<T: Pod>(&mut self, index: usize, value: T) {
         self.is_modified = true;
-        self.last_access = SystemTime::now();
         self.bitmap.set(index);
-        self.values[index] = value;
+        self.bytes_mut(index, 1)
+            .copy_from_slice(pod::as_bytes(&[value]));
     }
 
-    pub fn get_value(&mut self, index: usize) -> Option<u8> {
+    pub fn get_value<T: Pod>(&self, index: usize) -> Option<T> {
         if self.bitmap.get(index) {
-            self.last_access = SystemTime::now();
-            Some(self.values[index])
+            Some(pod::read(self.bytes(index, 1)))
         } else {
             None
         }
     }
 
+    pub fn set_values<T: Pod>(&mut self, offset: usize, values: &[T]) {
+        self.is_modified = true;
+        for index in offset..offset + values.len() {
+            self.bitmap.set(index);
+        }
+        self.bytes_mut(offset, values.len())
+            .copy_from_slice(pod::as_bytes(values));
+    }
+
+    // copy values starting at `offset` to `buf`, unset values are zeroed
+    // and their positions in `buf` are passed to `on_unset`
+    pub fn get_values<T, F>(&self, offset: usize, buf: &mut [T], mut on_unset: F)
+    where
+        T: Pod,
+        F: FnMut(usize),
+    {
+        let bytes = self.bytes(offset, buf.len());
+        pod::as_bytes_mut(buf).copy_from_slice(bytes);
+        for (i, value) in buf.iter_mut().enumerate() {
+            if !self.bitmap.get(offset + i) {
+                *value = T::zeroed();
+                on_unset(i);
+            }
+        }
+    }
+
+    // leaves a hole, values after `index` keep their offsets
     pub fn remove_value(&mut self, index: usize) {
+        if !self.bitmap.get(index) {
+            return;
+        }
         self.is_modified = true;
-        self.last_access = SystemTime::now();
         self.bitmap.reset(index);
-        self.values.remove(index);
+        self.bytes_mut(index, 1).fill(0);
+    }
+
+    // bytes of `count` values starting at value `index`
+    fn bytes(&self, index: usize, count: usize) -> &[u8] {
+        &self.values[index * self.element_size..(index + count) * self.element_size]
+    }
+
+    fn bytes_mut(&mut self, index: usize, count: usize) -> &mut [u8] {
+        &mut self.values[index * self.element_size..(index + count) * self.element_size]
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Page;
+    use super::{Page, PageLayout};
+    use crate::checksum::Checksum;
+    use crate::Error;
 
     #[test]
     fn set_value() {
-        let mut page = Page::new(0, 8, vec![0; 1 + 8]);
-        page.set_value(3, 1);
-        assert_eq!(page.is_modified, true);
-        assert_eq!(page.bitmap.get(3), true);
+        let mut page = Page::new(0, &PageLayout::new(8, 1), vec![0; 1 + 8]).unwrap();
+        page.set_value(3, 1u8);
+        assert!(page.is_modified);
+        assert!(page.bitmap.get(3));
         assert_eq!(page.values, vec![0, 0, 0, 1, 0, 0, 0, 0]);
     }
 
     #[test]
     fn get_value() {
-        let mut page = Page::new(0, 8, vec![0; 1 + 8]);
-        page.set_value(3, 1);
-        assert_eq!(page.get_value(3), Some(1));
-        assert_eq!(page.get_value(2), None);
+        let mut page = Page::new(0, &PageLayout::new(8, 1), vec![0; 1 + 8]).unwrap();
+        page.set_value(3, 1u8);
+        assert_eq!(page.get_value(3), Some(1u8));
+        assert_eq!(page.get_value::<u8>(2), None);
     }
 
     #[test]
     fn remove_value() {
-        let mut page = Page::new(0, 8, vec![0; 1 + 8]);
-        page.set_value(3, 1);
+        let mut page = Page::new(0, &PageLayout::new(8, 1), vec![0; 1 + 8]).unwrap();
+        page.set_value(3, 1u8);
+        page.set_value(4, 2u8);
+        page.remove_value(3);
+        assert!(page.is_modified);
+        assert!(!page.bitmap.get(3));
+        assert_eq!(page.values, vec![0, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(page.get_value(4), Some(2u8));
+    }
+
+    #[test]
+    fn remove_unset_value() {
+        let mut page = Page::new(0, &PageLayout::new(8, 1), vec![0; 1 + 8]).unwrap();
+        page.remove_value(3);
+        assert!(!page.is_modified);
+    }
+
+    #[test]
+    fn corrupt_bitmap() {
+        // page size 8 holds 7 values, so the 8th bitmap bit must be clear
+        assert!(Page::new(0, &PageLayout::new(8, 1), vec![0x80, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn touch() {
+        let mut page = Page::new(0, &PageLayout::new(8, 1), vec![0; 1 + 8]).unwrap();
+        assert_eq!(page.last_access, 0);
+        page.touch(7);
+        assert_eq!(page.last_access, 7);
+    }
+
+    #[test]
+    fn set_get_values() {
+        let mut page = Page::new(0, &PageLayout::new(8, 1), vec![0; 1 + 8]).unwrap();
+        page.set_values(1, &[7u8, 8]);
+        page.set_value(4, 9u8);
+
+        let mut buf = [0xffu8; 5];
+        let mut unset = Vec::new();
+        page.get_values(1, &mut buf, |i| unset.push(i));
+        assert_eq!(buf, [7, 8, 0, 9, 0]);
+        assert_eq!(unset, vec![2, 4]);
+    }
+
+    #[test]
+    fn layout() {
+        // 1/9 bitmap, 8/9 data for bytes
+        let layout = PageLayout::new(4096, 1);
+        assert_eq!((layout.data_size, layout.bitmap_size), (3640, 455));
+        // a bit per 8 bytes for u64, 4096 * 8 / 65 = 504 values
+        let layout = PageLayout::new(4096, 8);
+        assert_eq!((layout.data_size, layout.bitmap_size), (504, 63));
+        assert!(layout.data_size * 8 + layout.bitmap_size <= 4096);
+    }
+
+    #[test]
+    fn typed_values() {
+        let layout = PageLayout::new(64, 8);
+        let mut page = Page::new(0, &layout, vec![0; 64]).unwrap();
+        page.set_value(1, 2.5f64);
+        page.set_values(3, &[u64::MAX, 7]);
+        assert_eq!(page.get_value(1), Some(2.5f64));
+
+        let mut buf = [1u64; 4];
+        let mut unset = Vec::new();
+        page.get_values(1, &mut buf, |i| unset.push(i));
+        assert_eq!(buf, [2.5f64.to_bits(), 0, u64::MAX, 7]);
+        assert_eq!(unset, vec![1]);
+
         page.remove_value(3);
-        assert_eq!(page.is_modified, true);
-        assert_eq!(page.bitmap.get(3), false);
-        assert_eq!(page.values, vec![0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(page.get_value::<u64>(3), None);
+        assert_eq!(page.get_value(4), Some(7u64));
     }
 
     #[test]
-    fn access_time_update() {
-        let mut page = Page::new(0, 8, vec![0; 1 + 8]);
-        let old_modification_time = page.last_access;
+    fn checksum() {
+        for checksum in [Checksum::Crc32c, Checksum::XxHash64] {
+            let layout = PageLayout::with_checksum(64, 1, checksum);
+            assert!(layout.bitmap_size + layout.data_size + checksum.size() <= 64);
+
+            // a zeroed page is no valid page
+            assert!(matches!(
+                Page::new(3, &layout, vec![0; 64]),
+                Err(Error::CorruptPage { index: 3 })
+            ));
 
-        std::thread::sleep(std::time::Duration::from_millis(1));
-        page.set_value(2, 42);
+            let page = Page::empty(3, &layout);
+            assert!(Page::new(3, &layout, page.encode(&layout)).is_ok());
 
-        assert!(page.last_access > old_modification_time);
+            let mut page = Page::empty(3, &layout);
+            page.set_value(5, 1u8);
+            let mut bytes = page.encode(&layout);
+            assert_eq!(bytes.len(), 64);
+            assert!(Page::new(3, &layout, bytes.clone()).is_ok());
+            // same bytes at another index
+            assert!(matches!(
+                Page::new(4, &layout, bytes.clone()),
+                Err(Error::CorruptPage { index: 4 })
+            ));
+
+            bytes[20] ^= 0x10;
+            assert!(matches!(
+                Page::new(3, &layout, bytes),
+                Err(Error::CorruptPage { index: 3 })
+            ));
+        }
     }
 }
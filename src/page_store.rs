@@ -0,0 +1,619 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+// consecutive short transfers of one page before giving up,
+// a store that never finishes a page would otherwise spin forever
+const SHORT_RETRIES: usize = 8;
+
+// Storage that pages are read from and written to as whole blocks.
+// Page `index` of a `buf.len()` bytes transfer starts at byte
+// `index * buf.len()`, so page 0 is the start of the store for any size.
+//
+// A transfer should move the whole page. A short one, or one failing
+// with `Interrupted`, is repeated from the start of the page a few times
+// before `VirtualMemory` gives up, so a store can't split a page into
+// smaller transfers. Reads stay short only at the end of the store.
+// Pages it never wrote aren't read at all.
+pub trait PageStore {
+    // returns bytes read, fewer than `buf.len()` at the end of the store
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    // returns bytes written, the store grows to fit the page
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize>;
+
+    // push written pages down to the storage device, survives a power loss
+    fn sync(&mut self) -> io::Result<()>;
+
+    // size of the store in bytes
+    fn len(&mut self) -> io::Result<u64>;
+
+    fn is_empty(&mut self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    // hand buffered writes to the storage below, without waiting for them
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Page store that also transfers pages through a shared reference,
+// so several threads use it at once, `SharedVirtualMemory` takes these.
+// Every page is read and written at its own offset, there is no cursor.
+pub trait SharedPageStore: PageStore + Send + Sync {
+    fn read_page_shared(&self, index: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write_page_shared(&self, index: u64, buf: &[u8]) -> io::Result<usize>;
+
+    fn sync_shared(&self) -> io::Result<()>;
+
+    fn len_shared(&self) -> io::Result<u64>;
+}
+
+impl<S> PageStore for &mut S
+where
+    S: PageStore + ?Sized,
+{
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read_page(index, buf)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        (**self).write_page(index, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        (**self).len()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<S> PageStore for &S
+where
+    S: SharedPageStore + ?Sized,
+{
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read_page_shared(index, buf)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        (**self).write_page_shared(index, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync_shared()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        (**self).len_shared()
+    }
+}
+
+impl<S> SharedPageStore for &S
+where
+    S: SharedPageStore + ?Sized,
+{
+    fn read_page_shared(&self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read_page_shared(index, buf)
+    }
+
+    fn write_page_shared(&self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        (**self).write_page_shared(index, buf)
+    }
+
+    fn sync_shared(&self) -> io::Result<()> {
+        (**self).sync_shared()
+    }
+
+    fn len_shared(&self) -> io::Result<u64> {
+        (**self).len_shared()
+    }
+}
+
+impl<S> PageStore for Arc<S>
+where
+    S: SharedPageStore + ?Sized,
+{
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read_page_shared(index, buf)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        (**self).write_page_shared(index, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync_shared()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        (**self).len_shared()
+    }
+}
+
+impl<S> SharedPageStore for Arc<S>
+where
+    S: SharedPageStore + ?Sized,
+{
+    fn read_page_shared(&self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read_page_shared(index, buf)
+    }
+
+    fn write_page_shared(&self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        (**self).write_page_shared(index, buf)
+    }
+
+    fn sync_shared(&self) -> io::Result<()> {
+        (**self).sync_shared()
+    }
+
+    fn len_shared(&self) -> io::Result<u64> {
+        (**self).len_shared()
+    }
+}
+
+// any store behind a lock, transfers of different threads take turns
+impl<S> PageStore for Mutex<S>
+where
+    S: PageStore,
+{
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        lock_store(self.get_mut()).read_page(index, buf)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        lock_store(self.get_mut()).write_page(index, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        lock_store(self.get_mut()).sync()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        lock_store(self.get_mut()).len()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock_store(self.get_mut()).flush()
+    }
+}
+
+impl<S> SharedPageStore for Mutex<S>
+where
+    S: PageStore + Send,
+{
+    fn read_page_shared(&self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        lock_store(self.lock()).read_page(index, buf)
+    }
+
+    fn write_page_shared(&self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        lock_store(self.lock()).write_page(index, buf)
+    }
+
+    fn sync_shared(&self) -> io::Result<()> {
+        lock_store(self.lock()).sync()
+    }
+
+    fn len_shared(&self) -> io::Result<u64> {
+        lock_store(self.lock()).len()
+    }
+}
+
+// a transfer that panicked is retried as a whole, the store stays usable
+fn lock_store<G>(result: std::sync::LockResult<G>) -> G {
+    result.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// pread and pwrite, the file position is left alone
+#[cfg(any(unix, windows))]
+impl PageStore for File {
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_page_shared(index, buf)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        self.write_page_shared(index, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_shared()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        self.len_shared()
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl SharedPageStore for File {
+    fn read_page_shared(&self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        read_full_at(self, buf, page_offset(index, buf.len())?)
+    }
+
+    fn write_page_shared(&self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        write_all_at(self, buf, page_offset(index, buf.len())?)?;
+        Ok(buf.len())
+    }
+
+    fn sync_shared(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+
+    fn len_shared(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+// the cursor position is left alone
+impl PageStore for Cursor<Vec<u8>> {
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.get_ref();
+        let start = to_usize(page_offset(index, buf.len())?)?.min(bytes.len());
+        let read = buf.len().min(bytes.len() - start);
+        buf[..read].copy_from_slice(&bytes[start..start + read]);
+        Ok(read)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        let start = to_usize(page_offset(index, buf.len())?)?;
+        let bytes = self.get_mut();
+        if bytes.len() < start + buf.len() {
+            bytes.resize(start + buf.len(), 0);
+        }
+        bytes[start..start + buf.len()].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.get_ref().len() as u64)
+    }
+}
+
+// Pages kept in memory, only blocks that were written take space
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    blocks: BTreeMap<u64, Box<[u8]>>,
+    len: u64,
+}
+
+impl MemoryStore {
+    const BLOCK_SIZE: u64 = 4096;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // call `f` with every block touched by `len` bytes at `offset`:
+    // block index, range in the block, range in the transfer
+    fn blocks(offset: u64, len: usize, mut f: impl FnMut(u64, usize, std::ops::Range<usize>)) {
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let start = (position % Self::BLOCK_SIZE) as usize;
+            let count = (len - done).min(Self::BLOCK_SIZE as usize - start);
+            f(position / Self::BLOCK_SIZE, start, done..done + count);
+            done += count;
+        }
+    }
+}
+
+impl PageStore for MemoryStore {
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = page_offset(index, buf.len())?;
+        let read = buf.len().min(self.len.saturating_sub(offset) as usize);
+
+        Self::blocks(offset, read, |block, start, range| {
+            let chunk = &mut buf[range];
+            match self.blocks.get(&block) {
+                Some(block) => chunk.copy_from_slice(&block[start..start + chunk.len()]),
+                None => chunk.fill(0),
+            }
+        });
+        Ok(read)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        let offset = page_offset(index, buf.len())?;
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or_else(|| invalid_input("page past the end of the address space"))?;
+
+        Self::blocks(offset, buf.len(), |block, start, range| {
+            let chunk = &buf[range];
+            let block = self
+                .blocks
+                .entry(block)
+                .or_insert_with(|| vec![0; Self::BLOCK_SIZE as usize].into_boxed_slice());
+            block[start..start + chunk.len()].copy_from_slice(chunk);
+        });
+        self.len = self.len.max(end);
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+}
+
+// Page store over any `Read + Write + Seek` source, every transfer
+// seeks to its page first. `sync` flushes the source, use the `File`
+// store for durable files.
+#[derive(Debug)]
+pub struct RwsStore<RWS> {
+    inner: RWS,
+}
+
+impl<RWS> RwsStore<RWS>
+where
+    RWS: Read + Write + Seek,
+{
+    pub fn new(inner: RWS) -> Self {
+        RwsStore { inner }
+    }
+
+    pub fn get_ref(&self) -> &RWS {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut RWS {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> RWS {
+        self.inner
+    }
+}
+
+impl<RWS> PageStore for RwsStore<RWS>
+where
+    RWS: Read + Write + Seek,
+{
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner
+            .seek(SeekFrom::Start(page_offset(index, buf.len())?))?;
+        read_full(&mut self.inner, buf)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        self.inner
+            .seek(SeekFrom::Start(page_offset(index, buf.len())?))?;
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        self.inner.seek(SeekFrom::End(0))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// read page `index` to the whole `buf`, fails with `UnexpectedEof` if the
+// store ends inside the page, returns how many transfers were retried
+pub(crate) fn read_exact_page<S>(store: &mut S, index: u64, buf: &mut [u8]) -> io::Result<u64>
+where
+    S: PageStore + ?Sized,
+{
+    let mut retries = 0;
+    let mut short = 0;
+    loop {
+        match store.read_page(index, buf) {
+            Ok(read) if read >= buf.len() => return Ok(retries),
+            Ok(read) => {
+                // only the end of the store makes a read short for good
+                if page_offset(index, buf.len())? + read as u64 >= store.len()? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "page store ends inside the page",
+                    ));
+                }
+                short += 1;
+                if short == SHORT_RETRIES {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "page store keeps returning short reads",
+                    ));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+        retries += 1;
+    }
+}
+
+// write the whole `buf` as page `index`, a short write writes the page
+// again, returns how many transfers were retried
+pub(crate) fn write_all_page<S>(store: &mut S, index: u64, buf: &[u8]) -> io::Result<u64>
+where
+    S: PageStore + ?Sized,
+{
+    let mut retries = 0;
+    let mut short = 0;
+    loop {
+        match store.write_page(index, buf) {
+            Ok(written) if written >= buf.len() => return Ok(retries),
+            Ok(_) => {
+                short += 1;
+                if short == SHORT_RETRIES {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "page store keeps returning short writes",
+                    ));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+        retries += 1;
+    }
+}
+
+// fill `buf` from `source`, stops early only at the end of it
+pub(crate) fn read_full<R: Read + ?Sized>(source: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match source.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::write_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_write(file, buf, offset)
+}
+
+// read until `buf` is full or the end of the file, returns bytes read
+#[cfg(any(unix, windows))]
+fn read_full_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match read_at(file, &mut buf[read..], offset + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(any(unix, windows))]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    let mut written = 0;
+    while written < buf.len() {
+        match write_at(file, &buf[written..], offset + written as u64) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => written += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// byte offset of page `index` of `page_size` bytes
+pub(crate) fn page_offset(index: u64, page_size: usize) -> io::Result<u64> {
+    index
+        .checked_mul(page_size as u64)
+        .ok_or_else(|| invalid_input("page past the end of the address space"))
+}
+
+fn to_usize(offset: u64) -> io::Result<usize> {
+    usize::try_from(offset).map_err(|_| invalid_input("page past the end of memory"))
+}
+
+fn invalid_input(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_exact_page, MemoryStore, PageStore, RwsStore, SharedPageStore};
+    use std::io::{self, Cursor};
+    use std::sync::{Arc, Mutex};
+
+    // every store sees the same pages for the same transfers
+    fn round_trip(store: &mut dyn PageStore) {
+        assert!(store.is_empty().unwrap());
+        store.write_page(2, &[3; 10]).unwrap();
+        store.write_page(0, &[1; 4]).unwrap();
+        assert_eq!(store.len().unwrap(), 30);
+
+        let mut page = [0xff; 10];
+        assert_eq!(store.read_page(0, &mut page).unwrap(), 10);
+        assert_eq!(page, [1, 1, 1, 1, 0, 0, 0, 0, 0, 0]);
+
+        // bytes 4092..4104 cross a block of the memory store
+        store.write_page(341, &[7; 12]).unwrap();
+        let mut page = [0xff; 24];
+        assert_eq!(store.read_page(170, &mut page).unwrap(), 24);
+        assert_eq!(page[..12], [0; 12]);
+        assert_eq!(page[12..], [7; 12]);
+
+        // past the end
+        let mut page = [0xff; 10];
+        assert_eq!(store.read_page(1000, &mut page).unwrap(), 0);
+    }
+
+    #[test]
+    fn stores() {
+        round_trip(&mut MemoryStore::new());
+        round_trip(&mut Cursor::new(Vec::new()));
+        round_trip(&mut RwsStore::new(Cursor::new(Vec::new())));
+        round_trip(&mut tempfile::tempfile().unwrap());
+        round_trip(&mut Mutex::new(MemoryStore::new()));
+        round_trip(&mut &tempfile::tempfile().unwrap());
+    }
+
+    #[test]
+    fn shared_stores() {
+        let store = Arc::new(Mutex::new(MemoryStore::new()));
+        std::thread::scope(|s| {
+            for t in 0..4u8 {
+                let store = &store;
+                s.spawn(move || store.write_page_shared(t as u64, &[t; 8]).unwrap());
+            }
+        });
+
+        assert_eq!(store.len_shared().unwrap(), 32);
+        let mut page = [0u8; 8];
+        store.read_page_shared(3, &mut page).unwrap();
+        assert_eq!(page, [3; 8]);
+    }
+
+    #[test]
+    fn short_read_at_the_end() {
+        let mut store = MemoryStore::new();
+        store.write_page(0, &[1; 6]).unwrap();
+
+        let mut page = [0xff; 4];
+        assert_eq!(store.read_page(1, &mut page).unwrap(), 2);
+        assert_eq!(page[..2], [1, 1]);
+        let e = read_exact_page(&mut store, 1, &mut page).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
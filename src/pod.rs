@@ -0,0 +1,71 @@
+use std::{mem, ptr, slice};
+
+/// Plain old data: fixed-size values that can be copied to and from
+/// the swap source byte by byte, stored in native byte order,
+/// e.g. integers, floats and `#[repr(C)]` structs made of them.
+///
+/// # Safety
+///
+/// Implementors must have no padding bytes, since pages are copied out of
+/// values byte by byte, and every bit pattern of their size, all zeros
+/// included, must be a valid value, since pages are read back from whatever
+/// the swap source holds. They must also be `Copy + 'static`: no pointers,
+/// references or anything with a destructor.
+pub unsafe trait Pod: Copy + Send + 'static {
+    fn zeroed() -> Self {
+        // all zero bytes are a valid value of any `Pod`
+        unsafe { mem::zeroed() }
+    }
+}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+pub(crate) fn as_bytes<T: Pod>(values: &[T]) -> &[u8] {
+    // `Pod` has no padding, so every byte of `values` is initialized
+    unsafe { slice::from_raw_parts(values.as_ptr() as *const u8, mem::size_of_val(values)) }
+}
+
+pub(crate) fn as_bytes_mut<T: Pod>(values: &mut [T]) -> &mut [u8] {
+    // any bytes written through the slice form a valid `Pod`
+    unsafe { slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, mem::size_of_val(values)) }
+}
+
+pub(crate) fn read<T: Pod>(bytes: &[u8]) -> T {
+    assert!(
+        bytes.len() >= mem::size_of::<T>(),
+        "Not enough bytes for value"
+    );
+    // page bytes have no alignment guarantees
+    unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{as_bytes, as_bytes_mut, read, Pod};
+
+    #[test]
+    fn bytes_round_trip() {
+        let values = [1u32, 0xdead_beef];
+        let bytes = as_bytes(&values);
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(read::<u32>(&bytes[4..]), 0xdead_beef);
+
+        let mut copy = [0u32; 2];
+        as_bytes_mut(&mut copy).copy_from_slice(bytes);
+        assert_eq!(copy, values);
+    }
+
+    #[test]
+    fn zeroed() {
+        assert_eq!(f64::zeroed(), 0.0);
+        assert_eq!(<[u16; 3]>::zeroed(), [0; 3]);
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+// Decides which page leaves the buffer when a new page has to be loaded.
+//
+// Pages are identified by the frame they occupy in the buffer,
+// frames are numbered from 0 to the buffer size given to `reset`.
+// `now` is the logical time of virtual memory, it grows by one
+// with every page access, so policies don't need a clock of their own.
+pub trait ReplacementPolicy: Debug + Send {
+    // forget every frame and prepare to track `frames` of them
+    fn reset(&mut self, frames: usize);
+
+    // page was loaded into a free frame
+    fn insert(&mut self, frame: usize, now: u64);
+
+    // page in the frame was read or written
+    fn access(&mut self, frame: usize, now: u64);
+
+    // page left the frame, the frame is free again
+    fn remove(&mut self, frame: usize);
+
+    // frame whose page should be unloaded to free room,
+    // `None` only when no frame holds a page
+    fn victim(&mut self) -> Option<usize>;
+}
+
+// Doubly linked list threaded through an array indexed by frame,
+// every operation is O(1) and nothing is allocated after `reset`
+#[derive(Debug, Default)]
+struct FrameList {
+    // previous and next frame of every linked frame
+    links: Vec<Option<(usize, usize)>>,
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+impl FrameList {
+    const NONE: usize = usize::MAX;
+
+    fn reset(&mut self, frames: usize) {
+        self.links = vec![None; frames];
+        self.head = None;
+        self.tail = None;
+    }
+
+    fn push_back(&mut self, frame: usize) {
+        let prev = self.tail.unwrap_or(Self::NONE);
+        match self.tail {
+            Some(tail) => self.set_next(tail, frame),
+            None => self.head = Some(frame),
+        }
+        self.links[frame] = Some((prev, Self::NONE));
+        self.tail = Some(frame);
+    }
+
+    fn unlink(&mut self, frame: usize) {
+        let (prev, next) = match self.links[frame].take() {
+            Some(link) => link,
+            None => return,
+        };
+
+        if prev == Self::NONE {
+            self.head = Some(next).filter(|&e| e != Self::NONE);
+        } else {
+            self.set_next(prev, next);
+        }
+
+        if next == Self::NONE {
+            self.tail = Some(prev).filter(|&e| e != Self::NONE);
+        } else if let Some((next_prev, _)) = &mut self.links[next] {
+            *next_prev = prev;
+        }
+    }
+
+    fn front(&self) -> Option<usize> {
+        self.head
+    }
+
+    fn set_next(&mut self, frame: usize, next: usize) {
+        if let Some((_, frame_next)) = &mut self.links[frame] {
+            *frame_next = next;
+        }
+    }
+}
+
+// Least recently used page is evicted
+#[derive(Debug, Default)]
+pub struct Lru {
+    // least recently used frame first
+    order: FrameList,
+}
+
+impl Lru {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn reset(&mut self, frames: usize) {
+        self.order.reset(frames);
+    }
+
+    fn insert(&mut self, frame: usize, _now: u64) {
+        self.order.push_back(frame);
+    }
+
+    fn access(&mut self, frame: usize, _now: u64) {
+        self.order.unlink(frame);
+        self.order.push_back(frame);
+    }
+
+    fn remove(&mut self, frame: usize) {
+        self.order.unlink(frame);
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        self.order.front()
+    }
+}
+
+// Page loaded first is evicted first, accesses don't matter
+#[derive(Debug, Default)]
+pub struct Fifo {
+    // first loaded frame first
+    queue: FrameList,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn reset(&mut self, frames: usize) {
+        self.queue.reset(frames);
+    }
+
+    fn insert(&mut self, frame: usize, _now: u64) {
+        self.queue.push_back(frame);
+    }
+
+    fn access(&mut self, _frame: usize, _now: u64) {}
+
+    fn remove(&mut self, frame: usize) {
+        self.queue.unlink(frame);
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        self.queue.front()
+    }
+}
+
+// Second chance: the hand sweeps the frames, pages accessed since
+// the last sweep lose their reference bit instead of being evicted
+#[derive(Debug, Default)]
+pub struct Clock {
+    hand: usize,
+    // `None` for free frames, reference bit otherwise
+    referenced: Vec<Option<bool>>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for Clock {
+    fn reset(&mut self, frames: usize) {
+        self.hand = 0;
+        self.referenced = vec![None; frames];
+    }
+
+    fn insert(&mut self, frame: usize, _now: u64) {
+        self.referenced[frame] = Some(true);
+    }
+
+    fn access(&mut self, frame: usize, _now: u64) {
+        self.referenced[frame] = Some(true);
+    }
+
+    fn remove(&mut self, frame: usize) {
+        self.referenced[frame] = None;
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        let frames = self.referenced.len();
+        // the first sweep clears every reference bit,
+        // so the second one always finds a page
+        for _ in 0..2 * frames {
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % frames;
+
+            match &mut self.referenced[frame] {
+                Some(referenced) if *referenced => *referenced = false,
+                Some(_) => return Some(frame),
+                None => {}
+            }
+        }
+        None
+    }
+}
+
+// Least frequently used page is evicted,
+// ties go to the least recently used one
+#[derive(Debug, Default)]
+pub struct Lfu {
+    // access count and last access time of every page
+    usage: Vec<Option<(u64, u64)>>,
+    // the same usage ordered, the victim is the first entry
+    order: BTreeSet<(u64, u64, usize)>,
+}
+
+impl Lfu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn set_usage(&mut self, frame: usize, usage: Option<(u64, u64)>) {
+        if let Some((count, last_access)) = self.usage[frame] {
+            self.order.remove(&(count, last_access, frame));
+        }
+        if let Some((count, last_access)) = usage {
+            self.order.insert((count, last_access, frame));
+        }
+        self.usage[frame] = usage;
+    }
+}
+
+impl ReplacementPolicy for Lfu {
+    fn reset(&mut self, frames: usize) {
+        self.usage = vec![None; frames];
+        self.order.clear();
+    }
+
+    fn insert(&mut self, frame: usize, now: u64) {
+        self.set_usage(frame, Some((0, now)));
+    }
+
+    fn access(&mut self, frame: usize, now: u64) {
+        if let Some((count, _)) = self.usage[frame] {
+            self.set_usage(frame, Some((count + 1, now)));
+        }
+    }
+
+    fn remove(&mut self, frame: usize) {
+        self.set_usage(frame, None);
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        self.order.first().map(|&(_, _, frame)| frame)
+    }
+}
+
+// Uniformly random page is evicted, immune to access patterns
+// that defeat the other policies
+#[derive(Debug)]
+pub struct Random {
+    // xorshift64 state, never 0
+    state: u64,
+    resident: Vec<usize>,
+    // position of every frame in `resident`
+    position: Vec<Option<usize>>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random {
+            state: seed.max(1),
+            resident: Vec::new(),
+            position: Vec::new(),
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new(0x9e37_79b9_7f4a_7c15)
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn reset(&mut self, frames: usize) {
+        self.resident = Vec::with_capacity(frames);
+        self.position = vec![None; frames];
+    }
+
+    fn insert(&mut self, frame: usize, _now: u64) {
+        self.position[frame] = Some(self.resident.len());
+        self.resident.push(frame);
+    }
+
+    fn access(&mut self, _frame: usize, _now: u64) {}
+
+    fn remove(&mut self, frame: usize) {
+        if let Some(position) = self.position[frame].take() {
+            self.resident.swap_remove(position);
+            if let Some(&moved) = self.resident.get(position) {
+                self.position[moved] = Some(position);
+            }
+        }
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        if self.resident.is_empty() {
+            return None;
+        }
+        let position = self.next() % self.resident.len() as u64;
+        Some(self.resident[position as usize])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Clock, Fifo, Lfu, Lru, Random, ReplacementPolicy};
+
+    fn filled<P: ReplacementPolicy>(mut policy: P, frames: usize) -> P {
+        policy.reset(frames);
+        for frame in 0..frames {
+            policy.insert(frame, frame as u64);
+        }
+        policy
+    }
+
+    #[test]
+    fn lru() {
+        let mut lru = filled(Lru::new(), 3);
+        lru.access(0, 3);
+        assert_eq!(lru.victim(), Some(1));
+        lru.remove(1);
+        assert_eq!(lru.victim(), Some(2));
+    }
+
+    #[test]
+    fn lru_relinks_middle_and_ends() {
+        let mut lru = filled(Lru::new(), 4);
+        // order 0 1 2 3 -> 0 2 3 1 -> 2 3 1 0 -> 2 3 1
+        lru.access(1, 4);
+        lru.access(0, 5);
+        lru.remove(0);
+        assert_eq!(lru.victim(), Some(2));
+        lru.remove(2);
+        lru.remove(3);
+        assert_eq!(lru.victim(), Some(1));
+        lru.remove(1);
+        assert_eq!(lru.victim(), None);
+        lru.insert(3, 6);
+        assert_eq!(lru.victim(), Some(3));
+    }
+
+    #[test]
+    fn fifo() {
+        let mut fifo = filled(Fifo::new(), 3);
+        fifo.access(0, 3);
+        assert_eq!(fifo.victim(), Some(0));
+        fifo.remove(0);
+        fifo.insert(0, 4);
+        assert_eq!(fifo.victim(), Some(1));
+    }
+
+    #[test]
+    fn clock() {
+        let mut clock = filled(Clock::new(), 3);
+        // every page is referenced, the hand clears them all
+        // and comes back to the first one
+        assert_eq!(clock.victim(), Some(0));
+        clock.remove(0);
+        clock.insert(0, 3);
+        clock.access(1, 4);
+        assert_eq!(clock.victim(), Some(2));
+    }
+
+    #[test]
+    fn lfu() {
+        let mut lfu = filled(Lfu::new(), 3);
+        lfu.access(0, 3);
+        lfu.access(0, 4);
+        lfu.access(2, 5);
+        assert_eq!(lfu.victim(), Some(1));
+        lfu.remove(1);
+        assert_eq!(lfu.victim(), Some(2));
+        // equal counts, the earlier access loses
+        lfu.access(2, 6);
+        assert_eq!(lfu.victim(), Some(0));
+    }
+
+    #[test]
+    fn random() {
+        let mut random = filled(Random::new(42), 4);
+        random.remove(2);
+        for _ in 0..100 {
+            let victim = random.victim().unwrap();
+            assert!(victim < 4 && victim != 2);
+        }
+    }
+
+    #[test]
+    fn empty_has_no_victim() {
+        let policies: [Box<dyn ReplacementPolicy>; 5] = [
+            Box::new(Lru::new()),
+            Box::new(Fifo::new()),
+            Box::new(Clock::new()),
+            Box::new(Lfu::new()),
+            Box::new(Random::default()),
+        ];
+        for mut policy in policies {
+            policy.reset(3);
+            assert_eq!(policy.victim(), None);
+        }
+    }
+}
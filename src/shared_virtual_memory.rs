@@ -0,0 +1,519 @@
+use crate::checksum::Checksum;
+use crate::encryption::Encryption;
+use crate::error::{Error, Result};
+use crate::header::Header;
+use crate::page::{Page, PageLayout};
+use crate::page_store::{read_exact_page, write_all_page, SharedPageStore};
+use crate::pod::Pod;
+use crate::policy::{Lru, ReplacementPolicy};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+// Virtual memory that can be shared between threads, in the same format
+// as `VirtualMemory`. The buffer is split in shards, page `i` lives in
+// shard `i % shards` and every shard has its own lock, page table and
+// replacement policy, so pages of different shards are read, written
+// and swapped concurrently. Pages go through the swap source by shared
+// reference, see `SharedPageStore`.
+#[derive(Debug)]
+pub struct SharedVirtualMemory<S, T = u8>
+where
+    S: SharedPageStore,
+    T: Pod,
+{
+    swap_source: S,
+    shards: Vec<Mutex<Shard>>,
+    layout: PageLayout,
+    data_offset: u64,
+    // header fields that change, kept apart so no lock is needed
+    page_count: AtomicUsize,
+    len: AtomicUsize,
+    // held while a page past `page_count` is written with its holes
+    extending: Mutex<()>,
+    // header field of containers, kept as it is
+    container_len: usize,
+    // logical time, see `VirtualMemory::tick`
+    clock: AtomicU64,
+    values: PhantomData<T>,
+}
+
+#[derive(Debug)]
+struct Shard {
+    buffer: Vec<Option<Page>>,
+    page_table: HashMap<usize, usize>,
+    free_frames: Vec<usize>,
+    policy: Box<dyn ReplacementPolicy>,
+}
+
+impl<S> SharedVirtualMemory<S>
+where
+    S: SharedPageStore,
+{
+    pub fn new(swap_source: S, page_size: usize, buffer_size: usize, shards: usize) -> Self {
+        Self::try_new(swap_source, page_size, buffer_size, shards)
+            .expect("Failed to create shared virtual memory")
+    }
+
+    pub fn try_new(
+        swap_source: S,
+        page_size: usize,
+        buffer_size: usize,
+        shards: usize,
+    ) -> Result<Self> {
+        Self::try_new_typed(swap_source, page_size, buffer_size, shards)
+    }
+
+    // reopen swap source created by `new` or `VirtualMemory::new`
+    pub fn open(swap_source: S, buffer_size: usize, shards: usize) -> Result<Self> {
+        Self::open_typed(swap_source, buffer_size, shards)
+    }
+}
+
+impl<S, T> SharedVirtualMemory<S, T>
+where
+    S: SharedPageStore,
+    T: Pod,
+{
+    // same as `try_new`, for values of any `Pod` type
+    pub fn try_new_typed(
+        swap_source: S,
+        page_size: usize,
+        buffer_size: usize,
+        shards: usize,
+    ) -> Result<Self> {
+        let layout = PageLayout::for_values::<T>(page_size, Checksum::None, Encryption::None)?;
+        let vm = Self::with_header(swap_source, Header::new(layout), buffer_size, shards)?;
+        vm.write_header()?;
+        Ok(vm)
+    }
+
+    // same as `open`, for values of any `Pod` type
+    pub fn open_typed(swap_source: S, buffer_size: usize, shards: usize) -> Result<Self> {
+        if swap_source.len_shared()? < Header::SIZE as u64 {
+            return Err(Error::MissingHeader);
+        }
+
+        let mut bytes = [0u8; Header::SIZE];
+        read_exact_page(&mut &swap_source, 0, &mut bytes)?;
+
+        let header = Header::decode(&bytes)?;
+        header.check_values::<T>()?;
+        header.check_plain()?;
+        Self::with_header(swap_source, header, buffer_size, shards)
+    }
+
+    fn with_header(
+        swap_source: S,
+        header: Header,
+        buffer_size: usize,
+        shards: usize,
+    ) -> Result<Self> {
+        if shards == 0 {
+            return Err(Error::InvalidConfig(
+                "Shared virtual memory should have at least one shard",
+            ));
+        }
+        // every shard needs room for a page being loaded and one to evict
+        if buffer_size / shards < 2 {
+            return Err(Error::InvalidConfig(
+                "Shared virtual memory should have buffer size >= 2 * shards",
+            ));
+        }
+
+        let shards = (0..shards)
+            .map(|shard| {
+                // frames are spread evenly, the first shards take the remainder
+                let frames = buffer_size / shards + usize::from(shard < buffer_size % shards);
+                let mut policy = Box::new(Lru::new());
+                policy.reset(frames);
+                Mutex::new(Shard {
+                    buffer: (0..frames).map(|_| None).collect(),
+                    page_table: HashMap::with_capacity(frames),
+                    free_frames: (0..frames).rev().collect(),
+                    policy,
+                })
+            })
+            .collect();
+
+        Ok(SharedVirtualMemory {
+            swap_source,
+            shards,
+            layout: header.layout,
+            data_offset: header.data_offset,
+            page_count: AtomicUsize::new(header.page_count),
+            len: AtomicUsize::new(header.len),
+            extending: Mutex::new(()),
+            container_len: header.container_len,
+            clock: AtomicU64::new(0),
+            values: PhantomData,
+        })
+    }
+
+    // one past the highest index ever written
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn write(&self, index: usize, value: T) {
+        self.try_write(index, value)
+            .expect("Failed to write to shared virtual memory")
+    }
+
+    pub fn try_write(&self, index: usize, value: T) -> Result<()> {
+        let (page_index, value_offset) = self.locate(index);
+        self.with_page(page_index, |page| page.set_value(value_offset, value))?;
+        self.len.fetch_max(index + 1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    pub fn read(&self, index: usize) -> Option<T> {
+        self.try_read(index)
+            .expect("Failed to read from shared virtual memory")
+    }
+
+    pub fn try_read(&self, index: usize) -> Result<Option<T>> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+        let (page_index, value_offset) = self.locate(index);
+        self.with_page(page_index, |page| page.get_value(value_offset))
+    }
+
+    pub fn remove(&self, index: usize) -> Option<T> {
+        self.try_remove(index)
+            .expect("Failed to remove from shared virtual memory")
+    }
+
+    pub fn try_remove(&self, index: usize) -> Result<Option<T>> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+        let (page_index, value_offset) = self.locate(index);
+        self.with_page(page_index, |page| {
+            let value = page.get_value::<T>(value_offset);
+            page.remove_value(value_offset);
+            value
+        })
+    }
+
+    // write every modified page and the header to the swap source,
+    // pages stay in the buffer
+    pub fn flush(&self) -> Result<()> {
+        for shard in &self.shards {
+            let mut shard = self.lock(shard);
+            for frame in 0..shard.buffer.len() {
+                self.write_back(&mut shard, frame)?;
+            }
+        }
+        self.write_header()
+    }
+
+    // flush and make the swap source durable
+    pub fn sync(&self) -> Result<()> {
+        self.flush()?;
+        self.swap_source.sync_shared()?;
+        Ok(())
+    }
+
+    fn locate(&self, index: usize) -> (usize, usize) {
+        (index / self.layout.data_size, index % self.layout.data_size)
+    }
+
+    fn lock<'a>(&self, shard: &'a Mutex<Shard>) -> MutexGuard<'a, Shard> {
+        // a thread that panicked holding the lock left pages consistent,
+        // every page change is a single call
+        shard
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // run `f` on the page, loading it first if needed,
+    // only the shard of the page is locked
+    fn with_page<F, R>(&self, page_index: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Page) -> R,
+    {
+        let mut shard = self.lock(&self.shards[page_index % self.shards.len()]);
+        let frame = match shard.page_table.get(&page_index) {
+            Some(&frame) => {
+                let now = self.tick();
+                shard.policy.access(frame, now);
+                page_in(&mut shard, frame).touch(now);
+                frame
+            }
+            // a loaded page is already stamped with the time of this access
+            None => self.load_page(&mut shard, page_index)?,
+        };
+
+        Ok(f(page_in(&mut shard, frame)))
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn page_offset(&self, page_index: usize) -> Result<u64> {
+        page_index
+            .checked_mul(self.layout.page_size)
+            .and_then(|offset| (offset as u64).checked_add(self.data_offset))
+            .ok_or(Error::IndexOutOfRange {
+                index: page_index.saturating_mul(self.layout.data_size),
+            })
+    }
+
+    // page of the swap source holding `page_index`, see `VirtualMemory::store_index`
+    fn store_index(&self, page_index: usize) -> Result<u64> {
+        Ok(self.page_offset(page_index)? / self.layout.page_size as u64)
+    }
+
+    fn write_header(&self) -> Result<()> {
+        let mut header = Header::new(self.layout);
+        header.data_offset = self.data_offset;
+        header.page_count = self.page_count.load(Ordering::Acquire);
+        header.len = self.len();
+        header.container_len = self.container_len;
+        write_all_page(&mut &self.swap_source, 0, &header.encode())?;
+        Ok(())
+    }
+
+    fn load_page(&self, shard: &mut Shard, page_index: usize) -> Result<usize> {
+        let store_index = self.store_index(page_index)?;
+
+        if shard.free_frames.is_empty() {
+            let frame = shard
+                .policy
+                .victim()
+                .expect("Replacement policy found no page to evict");
+            self.write_back(shard, frame)?;
+            let page = shard.buffer[frame]
+                .take()
+                .expect("Replacement policy chose a free frame");
+            shard.page_table.remove(&page.index);
+            shard.free_frames.push(frame);
+            shard.policy.remove(frame);
+        }
+
+        // never written back, whatever the swap source holds there isn't ours
+        let page = if page_index < self.page_count.load(Ordering::Acquire) {
+            let mut bytes = vec![0u8; self.layout.page_size];
+            read_exact_page(&mut &self.swap_source, store_index, &mut bytes)?;
+            Page::new(page_index, &self.layout, bytes)?
+        } else {
+            Page::empty(page_index, &self.layout)
+        };
+        let frame = shard.free_frames.pop().expect("Failed to free a frame");
+        let now = self.tick();
+        shard.buffer[frame] = Some(page);
+        page_in(shard, frame).touch(now);
+        shard.page_table.insert(page_index, frame);
+        shard.policy.insert(frame, now);
+        Ok(frame)
+    }
+
+    fn write_back(&self, shard: &mut Shard, frame: usize) -> Result<()> {
+        let page = match &mut shard.buffer[frame] {
+            Some(page) if page.is_modified => page,
+            _ => return Ok(()),
+        };
+
+        let store_index = self.store_index(page.index)?;
+        let bytes = page.encode(&self.layout);
+        // writes past the end take turns, so the empty page written for
+        // a hole never lands after the page itself, see `PageLayout::holes`
+        let _extending = (page.index >= self.page_count.load(Ordering::Acquire)).then(|| {
+            self.extending
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        });
+        let page_count = self.page_count.load(Ordering::Acquire);
+        for hole in self.layout.holes(page_count, page.index) {
+            let empty = Page::empty(hole, &self.layout).encode(&self.layout);
+            write_all_page(&mut &self.swap_source, self.store_index(hole)?, &empty)?;
+        }
+        write_all_page(&mut &self.swap_source, store_index, &bytes)?;
+
+        self.page_count.fetch_max(page.index + 1, Ordering::AcqRel);
+        page.is_modified = false;
+        Ok(())
+    }
+}
+
+fn page_in(shard: &mut Shard, frame: usize) -> &mut Page {
+    shard.buffer[frame]
+        .as_mut()
+        .expect("Failed to find page in buffer")
+}
+
+impl<S, T> Drop for SharedVirtualMemory<S, T>
+where
+    S: SharedPageStore,
+    T: Pod,
+{
+    fn drop(&mut self) {
+        // errors can't be reported from drop, use `flush` to see them
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SharedVirtualMemory;
+    use crate::page_store::PageStore;
+    use crate::{Checksum, Error, MemoryStore, Options, VirtualMemory};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn shared_between_threads() {
+        let swap_file = tempfile::tempfile().unwrap();
+        let vm = SharedVirtualMemory::<_, u32>::try_new_typed(swap_file, 64, 8, 4).unwrap();
+
+        thread::scope(|s| {
+            for t in 0..8u32 {
+                let vm = &vm;
+                s.spawn(move || {
+                    // threads write interleaved pages and check their own values
+                    for i in (t as usize..4000).step_by(8) {
+                        vm.write(i, i as u32 * 3);
+                    }
+                    for i in (t as usize..4000).step_by(8) {
+                        assert_eq!(vm.read(i), Some(i as u32 * 3));
+                    }
+                });
+            }
+        });
+
+        assert_eq!(vm.len(), 4000);
+        for i in 0..4000 {
+            assert_eq!(vm.read(i), Some(i as u32 * 3));
+        }
+    }
+
+    #[test]
+    fn readers_and_writers() {
+        let swap_file = tempfile::tempfile().unwrap();
+        let vm = Arc::new(SharedVirtualMemory::new(swap_file, 72, 6, 3));
+        for i in 0..1000 {
+            vm.write(i, (i % 251) as u8);
+        }
+
+        let handles: Vec<_> = (0..6)
+            .map(|t| {
+                let vm = Arc::clone(&vm);
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        for i in (0..1000).rev() {
+                            if t % 2 == 0 {
+                                assert_eq!(vm.read(i), Some((i % 251) as u8));
+                            } else {
+                                // writers store the same values, readers never see others
+                                vm.write(i, (i % 251) as u8);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn remove_and_reopen() {
+        let swap_file = tempfile::tempfile().unwrap();
+        {
+            let vm = SharedVirtualMemory::new(&swap_file, 72, 4, 2);
+            for i in 0..500 {
+                vm.write(i, 7);
+            }
+            assert_eq!(vm.remove(100), Some(7));
+            assert_eq!(vm.remove(100), None);
+        }
+
+        let vm = SharedVirtualMemory::open(&swap_file, 4, 2).unwrap();
+        assert_eq!(vm.len(), 500);
+        assert_eq!(vm.read(100), None);
+        assert_eq!(vm.read(499), Some(7));
+    }
+
+    #[test]
+    fn any_store_behind_a_lock() {
+        let vm = SharedVirtualMemory::new(Mutex::new(MemoryStore::new()), 64, 4, 2);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let vm = &vm;
+                s.spawn(move || {
+                    for i in (t..1000).step_by(4) {
+                        vm.write(i, i as u8);
+                    }
+                });
+            }
+        });
+        for i in 0..1000 {
+            assert_eq!(vm.read(i), Some(i as u8));
+        }
+    }
+
+    #[test]
+    fn sparse_write_fills_holes() {
+        let swap_file = tempfile::tempfile().unwrap();
+        let options = Options {
+            checksum: Checksum::Crc32c,
+            ..Options::default()
+        };
+        VirtualMemory::<_, u8>::try_new_with_options(&swap_file, 64, 4, options).unwrap();
+
+        let vm = SharedVirtualMemory::open(&swap_file, 4, 2).unwrap();
+        vm.write(10000, 1);
+        vm.flush().unwrap();
+        // every page below the one written passes its checksum
+        assert_eq!(vm.try_read(100).unwrap(), None);
+        drop(vm);
+
+        let mut vm = VirtualMemory::open(&swap_file, 4).unwrap();
+        assert_eq!(vm.try_read(100).unwrap(), None);
+        assert_eq!(vm.try_read(10000).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn pages_past_page_count() {
+        let swap_file = tempfile::tempfile().unwrap();
+        SharedVirtualMemory::new(&swap_file, 64, 4, 2).write(0, 1);
+        // bytes of a page that was never written back
+        (&swap_file).write_page(2, &[0xff; 64]).unwrap();
+
+        let vm = SharedVirtualMemory::open(&swap_file, 4, 2).unwrap();
+        vm.write(64, 2);
+        assert_eq!(vm.read(65), None);
+    }
+
+    #[test]
+    fn truncated_page() {
+        let swap_file = tempfile::tempfile().unwrap();
+        SharedVirtualMemory::new(&swap_file, 64, 4, 2).write(64, 1);
+        swap_file.set_len(64 * 3 - 10).unwrap();
+
+        let vm = SharedVirtualMemory::open(&swap_file, 4, 2).unwrap();
+        assert!(matches!(
+            vm.try_read(64),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn too_few_frames() {
+        let swap_file = tempfile::tempfile().unwrap();
+        assert!(matches!(
+            SharedVirtualMemory::try_new(&swap_file, 72, 5, 3),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,470 @@
+use crate::checksum::crc32c;
+use crate::error::{Error, Result};
+use crate::page_store::{read_exact_page, write_all_page, PageStore};
+use crate::stats::Stats;
+use std::collections::BTreeSet;
+
+// unit of space for compressed pages, blocks are 2^class sectors
+const SECTOR_SIZE: u64 = 64;
+const SIGNATURE: &[u8; 4] = b"VSL1";
+const SUPERBLOCK_CHECKSUM_OFFSET: usize = SECTOR_SIZE as usize - 4;
+// next table block, before the slots of a table block
+const TABLE_BLOCK_HEADER: usize = 8;
+const SLOT_SIZE: usize = 16;
+
+// Where a page is in the swap source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    // first sector of the block, never 0 as that is the superblock
+    start: u64,
+    class: u8,
+    // bytes of the page in the block
+    len: u32,
+    // stored as is when compressing didn't make it smaller
+    is_compressed: bool,
+}
+
+// Compressed pages have any length, so they can't be found by
+// multiplying their index. They are kept in blocks of 2^class sectors
+// after the header, every block aligned to its size so it moves in one
+// `PageStore` transfer, and a table maps page index to block:
+//
+// sector 0, superblock:
+//  0..4   signature "VSL1"
+//  8..16  end, sectors ever handed out
+// 16..24  first table block, 0 for none
+// 24..32  slots in the table
+// 60..64  CRC-32C of bytes 0..60
+//
+// table blocks, the largest block size:
+//  0..8   next table block, 0 for none
+//  then 16 bytes a slot: start u64, len u32, class u8, compressed u8,
+//  a start of 0 for pages without a block
+//
+// Free blocks aren't stored, they are the gaps between used ones.
+#[derive(Debug)]
+pub(crate) struct SlotTable {
+    // byte offset of sector 0, aligned to the largest block
+    base: u64,
+    // class of the largest block, big enough for a whole page
+    max_class: u8,
+    slots: Vec<Option<Slot>>,
+    table_blocks: Vec<u64>,
+    // table blocks to write on the next flush
+    dirty_blocks: BTreeSet<usize>,
+    // free blocks of every class
+    free: Vec<Vec<u64>>,
+    // start and class of blocks given up since the last flush, the table
+    // in the swap source may still point at them until it is written
+    pending: Vec<(u64, u8)>,
+    end: u64,
+    // superblock changed since the last flush
+    is_modified: bool,
+}
+
+impl SlotTable {
+    pub fn new(data_offset: u64, page_size: usize) -> Self {
+        let sectors = (page_size as u64).div_ceil(SECTOR_SIZE).next_power_of_two();
+        let max_class = sectors.trailing_zeros() as u8;
+        let largest = SECTOR_SIZE << max_class;
+
+        SlotTable {
+            base: data_offset.div_ceil(largest) * largest,
+            max_class,
+            slots: Vec::new(),
+            table_blocks: Vec::new(),
+            dirty_blocks: BTreeSet::new(),
+            free: vec![Vec::new(); max_class as usize + 1],
+            pending: Vec::new(),
+            // the superblock
+            end: 1,
+            is_modified: true,
+        }
+    }
+
+    pub fn open<S>(store: &mut S, data_offset: u64, page_size: usize) -> Result<Self>
+    where
+        S: PageStore,
+    {
+        let mut table = Self::new(data_offset, page_size);
+        table.is_modified = false;
+
+        let mut stats = Stats::default();
+        let superblock = table.read_block(store, 0, 0, &mut stats)?;
+        let checksum = u32::from_le_bytes(read(&superblock, SUPERBLOCK_CHECKSUM_OFFSET));
+        if &superblock[0..4] != SIGNATURE
+            || checksum != crc32c(&superblock[..SUPERBLOCK_CHECKSUM_OFFSET])
+        {
+            return Err(Error::CorruptHeader("slot table"));
+        }
+        table.end = u64::from_le_bytes(read(&superblock, 8));
+        let mut next = u64::from_le_bytes(read(&superblock, 16));
+        let len = to_usize(u64::from_le_bytes(read(&superblock, 24)))?;
+
+        let per_block = table.slots_per_block();
+        while table.slots.len() < len {
+            if next == 0 || next >= table.end || table.table_blocks.len() * per_block >= len {
+                return Err(Error::CorruptHeader("slot table"));
+            }
+            let block = table.read_block(store, next, table.max_class, &mut stats)?;
+            table.table_blocks.push(next);
+            next = u64::from_le_bytes(read(&block, 0));
+
+            let count = per_block.min(len - table.slots.len());
+            for i in 0..count {
+                let slot = table.decode_slot(&block[TABLE_BLOCK_HEADER + i * SLOT_SIZE..])?;
+                table.slots.push(slot);
+            }
+        }
+
+        table.rebuild_free_lists();
+        Ok(table)
+    }
+
+    // bytes of page `index` and whether they are compressed,
+    // `None` if it has no block
+    pub fn read<S>(
+        &mut self,
+        store: &mut S,
+        index: usize,
+        stats: &mut Stats,
+    ) -> Result<Option<(Vec<u8>, bool)>>
+    where
+        S: PageStore,
+    {
+        let slot = match self.slots.get(index).copied().flatten() {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+
+        let mut bytes = self.read_block(store, slot.start, slot.class, stats)?;
+        bytes.truncate(slot.len as usize);
+        Ok(Some((bytes, slot.is_compressed)))
+    }
+
+    // write `bytes` of page `index` to a block of their size,
+    // returns the size of the block
+    pub fn write<S>(
+        &mut self,
+        store: &mut S,
+        index: usize,
+        bytes: &[u8],
+        is_compressed: bool,
+        stats: &mut Stats,
+    ) -> Result<u64>
+    where
+        S: PageStore,
+    {
+        let sectors = (bytes.len() as u64)
+            .div_ceil(SECTOR_SIZE)
+            .next_power_of_two();
+        let class = sectors.trailing_zeros() as u8;
+        if self.slots.len() <= index {
+            self.slots.resize(index + 1, None);
+        }
+        let old = self.slots[index];
+        let start = match old {
+            Some(slot) if slot.class == class => slot.start,
+            _ => self.alloc(class),
+        };
+
+        let mut block = bytes.to_vec();
+        block.resize((SECTOR_SIZE << class) as usize, 0);
+        if let Err(e) = self.write_block(store, start, class, &block, stats) {
+            if old.is_none_or(|old| old.start != start) {
+                self.free[class as usize].push(start);
+            }
+            return Err(e);
+        }
+        // the old block is free only once the page is elsewhere
+        // and the table saying so is flushed
+        if let Some(old) = old.filter(|old| old.start != start) {
+            self.pending.push((old.start, old.class));
+        }
+
+        self.slots[index] = Some(Slot {
+            start,
+            class,
+            len: bytes.len() as u32,
+            is_compressed,
+        });
+        self.dirty_blocks.insert(index / self.slots_per_block());
+        Ok(SECTOR_SIZE << class)
+    }
+
+    // write the table blocks and superblock changed since the last flush
+    pub fn flush<S>(&mut self, store: &mut S, stats: &mut Stats) -> Result<()>
+    where
+        S: PageStore,
+    {
+        let per_block = self.slots_per_block();
+        while self.table_blocks.len() * per_block < self.slots.len() {
+            let block = self.alloc(self.max_class);
+            // the previous block links to the new one
+            if let Some(last) = self.table_blocks.len().checked_sub(1) {
+                self.dirty_blocks.insert(last);
+            }
+            self.dirty_blocks.insert(self.table_blocks.len());
+            self.table_blocks.push(block);
+            self.is_modified = true;
+        }
+
+        let dirty = std::mem::take(&mut self.dirty_blocks);
+        for (n, block_index) in dirty.iter().enumerate() {
+            let bytes = self.encode_table_block(*block_index);
+            let start = self.table_blocks[*block_index];
+            if let Err(e) = self.write_block(store, start, self.max_class, &bytes, stats) {
+                self.dirty_blocks.extend(dirty.iter().skip(n));
+                return Err(e);
+            }
+        }
+
+        if self.is_modified {
+            let superblock = self.encode_superblock();
+            self.write_block(store, 0, 0, &superblock, stats)?;
+            self.is_modified = false;
+        }
+
+        for (start, class) in std::mem::take(&mut self.pending) {
+            self.free[class as usize].push(start);
+        }
+        Ok(())
+    }
+
+    fn slots_per_block(&self) -> usize {
+        ((SECTOR_SIZE << self.max_class) as usize - TABLE_BLOCK_HEADER) / SLOT_SIZE
+    }
+
+    fn encode_superblock(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; SECTOR_SIZE as usize];
+        bytes[0..4].copy_from_slice(SIGNATURE);
+        bytes[8..16].copy_from_slice(&self.end.to_le_bytes());
+        let first = self.table_blocks.first().copied().unwrap_or(0);
+        bytes[16..24].copy_from_slice(&first.to_le_bytes());
+        bytes[24..32].copy_from_slice(&(self.slots.len() as u64).to_le_bytes());
+
+        let checksum = crc32c(&bytes[..SUPERBLOCK_CHECKSUM_OFFSET]);
+        bytes[SUPERBLOCK_CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    fn encode_table_block(&self, block_index: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; (SECTOR_SIZE << self.max_class) as usize];
+        let next = self.table_blocks.get(block_index + 1).copied().unwrap_or(0);
+        bytes[0..8].copy_from_slice(&next.to_le_bytes());
+
+        let per_block = self.slots_per_block();
+        let first = block_index * per_block;
+        let slots = self.slots.iter().skip(first).take(per_block);
+        for (i, slot) in slots.enumerate() {
+            if let Some(slot) = slot {
+                let at = TABLE_BLOCK_HEADER + i * SLOT_SIZE;
+                bytes[at..at + 8].copy_from_slice(&slot.start.to_le_bytes());
+                bytes[at + 8..at + 12].copy_from_slice(&slot.len.to_le_bytes());
+                bytes[at + 12] = slot.class;
+                bytes[at + 13] = slot.is_compressed as u8;
+            }
+        }
+        bytes
+    }
+
+    fn decode_slot(&self, bytes: &[u8]) -> Result<Option<Slot>> {
+        let start = u64::from_le_bytes(read(bytes, 0));
+        if start == 0 {
+            return Ok(None);
+        }
+
+        let slot = Slot {
+            start,
+            len: u32::from_le_bytes(read(bytes, 8)),
+            class: bytes[12],
+            is_compressed: bytes[13] != 0,
+        };
+        let size = SECTOR_SIZE << slot.class.min(63);
+        let is_valid = slot.class <= self.max_class
+            && start % (1 << slot.class) == 0
+            && start + (1 << slot.class) <= self.end
+            && slot.len as u64 <= size;
+        if !is_valid {
+            return Err(Error::CorruptHeader("slot table"));
+        }
+        Ok(Some(slot))
+    }
+
+    // block of 2^`class` sectors, from the free lists or past the end
+    fn alloc(&mut self, class: u8) -> u64 {
+        if let Some(start) = self.free[class as usize].pop() {
+            return start;
+        }
+
+        // split the smallest larger free block, keeping the halves
+        for larger in class + 1..=self.max_class {
+            if let Some(start) = self.free[larger as usize].pop() {
+                for half in (class..larger).rev() {
+                    self.free[half as usize].push(start + (1 << half));
+                }
+                return start;
+            }
+        }
+
+        let size = 1u64 << class;
+        let start = self.end.div_ceil(size) * size;
+        self.free_range(self.end, start);
+        self.end = start + size;
+        self.is_modified = true;
+        start
+    }
+
+    // hand sectors `from..to` to the free lists as aligned blocks
+    fn free_range(&mut self, mut from: u64, to: u64) {
+        while from < to {
+            let aligned = from.trailing_zeros().min(self.max_class as u32);
+            let fits = (to - from).ilog2();
+            let class = aligned.min(fits);
+            self.free[class as usize].push(from);
+            from += 1 << class;
+        }
+    }
+
+    fn rebuild_free_lists(&mut self) {
+        let largest = 1u64 << self.max_class;
+        let mut used: Vec<(u64, u64)> = vec![(0, 1)];
+        used.extend(self.table_blocks.iter().map(|&start| (start, largest)));
+        used.extend(
+            self.slots
+                .iter()
+                .flatten()
+                .map(|slot| (slot.start, 1 << slot.class)),
+        );
+        used.sort_unstable();
+
+        let mut free_from = 0;
+        for (start, len) in used {
+            if start > free_from {
+                self.free_range(free_from, start);
+            }
+            free_from = free_from.max(start + len);
+        }
+        self.free_range(free_from, self.end);
+    }
+
+    fn read_block<S>(
+        &self,
+        store: &mut S,
+        start: u64,
+        class: u8,
+        stats: &mut Stats,
+    ) -> Result<Vec<u8>>
+    where
+        S: PageStore,
+    {
+        let mut bytes = vec![0u8; (SECTOR_SIZE << class) as usize];
+        stats.retries += read_exact_page(store, self.block_index(start, class), &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_block<S>(
+        &self,
+        store: &mut S,
+        start: u64,
+        class: u8,
+        bytes: &[u8],
+        stats: &mut Stats,
+    ) -> Result<()>
+    where
+        S: PageStore,
+    {
+        stats.retries += write_all_page(store, self.block_index(start, class), bytes)?;
+        Ok(())
+    }
+
+    // `PageStore` page of the block, blocks are aligned to their size
+    fn block_index(&self, start: u64, class: u8) -> u64 {
+        (self.base / SECTOR_SIZE + start) >> class
+    }
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+fn to_usize(value: u64) -> Result<usize> {
+    usize::try_from(value).map_err(|_| Error::CorruptHeader("slot table"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::SlotTable;
+    use crate::page_store::MemoryStore;
+    use crate::stats::Stats;
+
+    #[test]
+    fn free_blocks_are_reused() {
+        let mut table = SlotTable::new(64, 256);
+        // sector 0 is the superblock, a block of 4 sectors starts at 4
+        assert_eq!(table.alloc(2), 4);
+        assert_eq!(table.free, vec![vec![1], vec![2], vec![]]);
+        assert_eq!(table.alloc(0), 1);
+        // splits the free block of 2 sectors
+        assert_eq!(table.alloc(0), 2);
+        assert_eq!(table.alloc(0), 3);
+        assert_eq!(table.alloc(1), 8);
+        assert_eq!(table.end, 10);
+    }
+
+    #[test]
+    fn freed_blocks_wait_for_flush() {
+        let mut store = MemoryStore::new();
+        let mut stats = Stats::default();
+        let mut table = SlotTable::new(64, 256);
+        table
+            .write(&mut store, 0, &[1; 256], false, &mut stats)
+            .unwrap();
+        table.flush(&mut store, &mut stats).unwrap();
+        let old = table.slots[0].unwrap().start;
+
+        // page 0 moves to a smaller block, the table in the store
+        // still points at the old one
+        table
+            .write(&mut store, 0, &[2; 100], true, &mut stats)
+            .unwrap();
+        table
+            .write(&mut store, 1, &[3; 256], false, &mut stats)
+            .unwrap();
+        assert_ne!(table.slots[1].unwrap().start, old);
+
+        table.flush(&mut store, &mut stats).unwrap();
+        table
+            .write(&mut store, 2, &[4; 256], false, &mut stats)
+            .unwrap();
+        assert_eq!(table.slots[2].unwrap().start, old);
+    }
+
+    #[test]
+    fn reopen() {
+        let mut store = MemoryStore::new();
+        let mut stats = Stats::default();
+        let page = |i: u8| vec![i; 256];
+
+        let mut table = SlotTable::new(64, 256);
+        for i in 0..40 {
+            // pages 8.. take half a block
+            let bytes = &page(i as u8)[..if i < 8 { 256 } else { 100 }];
+            table
+                .write(&mut store, i, bytes, i % 2 == 0, &mut stats)
+                .unwrap();
+        }
+        table.flush(&mut store, &mut stats).unwrap();
+        let free = table.free.clone();
+
+        let mut table = SlotTable::open(&mut store, 64, 256).unwrap();
+        assert_eq!(table.free, free);
+        for i in 0..40 {
+            let (bytes, is_compressed) = table.read(&mut store, i, &mut stats).unwrap().unwrap();
+            assert_eq!(bytes.len(), if i < 8 { 256 } else { 100 });
+            assert!(bytes.iter().all(|&byte| byte == i as u8));
+            assert_eq!(is_compressed, i % 2 == 0);
+        }
+        assert_eq!(table.read(&mut store, 40, &mut stats).unwrap(), None);
+    }
+}
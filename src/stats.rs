@@ -0,0 +1,29 @@
+// Page traffic between the buffer and the swap source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    // pages read from the swap source
+    pub page_reads: u64,
+    // pages written to the swap source
+    pub page_writes: u64,
+    // pages loaded as zeros without I/O, they were never written
+    pub zero_pages: u64,
+    // pages found corrupt on load, quarantined or not
+    pub corrupt_pages: u64,
+    // transfers repeated after a short count or `ErrorKind::Interrupted`
+    pub retries: u64,
+    // bytes of the pages written
+    pub page_bytes: u64,
+    // bytes those pages took in the swap source,
+    // a compressed page takes its whole block
+    pub stored_bytes: u64,
+}
+
+impl Stats {
+    // page bytes per stored byte, 1 before anything is written
+    pub fn compression_ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+        self.page_bytes as f64 / self.stored_bytes as f64
+    }
+}
@@ -0,0 +1,251 @@
+use crate::error::{Error, Result};
+use crate::page_store::PageStore;
+use crate::virtual_memory::VirtualMemory;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+const SIGNATURE: &[u8; 4] = b"VAR1";
+// signature and element size, the elements follow
+const META_SIZE: usize = SIGNATURE.len() + 8;
+
+// Growable array of serializable elements kept in virtual memory.
+// Every element is serialized with bincode into a slot of `element_size`
+// bytes, shorter elements are padded with zeros. The element size is
+// kept in front of the elements, so `open` can check it
+#[derive(Debug)]
+pub struct VArray<T, S>
+where
+    S: PageStore,
+    T: Serialize + DeserializeOwned,
+{
+    vm: VirtualMemory<S>,
+    element_size: usize,
+    len: usize,
+    // slot of the last serialized or read element
+    buffer: Vec<u8>,
+    elements: PhantomData<T>,
+}
+
+impl<T, S> VArray<T, S>
+where
+    S: PageStore,
+    T: Serialize + DeserializeOwned,
+{
+    const PAGE_SIZE: usize = 4096;
+
+    pub fn new(swap_source: S, element_size: usize, buffer_size: usize) -> Result<Self> {
+        Self::check_element_size(element_size)?;
+        let mut vm = VirtualMemory::try_new(swap_source, Self::PAGE_SIZE, buffer_size)?;
+        let mut meta = Vec::with_capacity(META_SIZE);
+        meta.extend_from_slice(SIGNATURE);
+        meta.extend_from_slice(&(element_size as u64).to_le_bytes());
+        vm.try_write_slice(0, &meta)?;
+        Ok(Self::with_vm(vm, element_size))
+    }
+
+    // reopen swap source created by `new` with the same `element_size`
+    pub fn open(swap_source: S, element_size: usize, buffer_size: usize) -> Result<Self> {
+        Self::check_element_size(element_size)?;
+        let mut vm = VirtualMemory::open(swap_source, buffer_size)?;
+        let mut meta = [0u8; META_SIZE];
+        vm.try_read_into(0, &mut meta)?;
+        if &meta[..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::InvalidConfig("Swap source doesn't hold a VArray"));
+        }
+        let stored = u64::from_le_bytes(
+            meta[SIGNATURE.len()..]
+                .try_into()
+                .expect("Element size out of bounds"),
+        );
+        if stored != element_size as u64 {
+            return Err(Error::InvalidConfig(
+                "Element size doesn't match the swap source",
+            ));
+        }
+        Ok(Self::with_vm(vm, element_size))
+    }
+
+    fn check_element_size(element_size: usize) -> Result<()> {
+        if element_size == 0 {
+            return Err(Error::InvalidConfig("VArray should have element size > 0"));
+        }
+        Ok(())
+    }
+
+    fn with_vm(vm: VirtualMemory<S>, element_size: usize) -> Self {
+        VArray {
+            len: vm.len().saturating_sub(META_SIZE) / element_size,
+            vm,
+            element_size,
+            buffer: vec![0; element_size],
+            elements: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn element_size(&self) -> usize {
+        self.element_size
+    }
+
+    pub fn push(&mut self, element: &T) -> Result<()> {
+        self.write_slot(self.len, element)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn get(&mut self, index: usize) -> Result<T> {
+        let offset = self.slot_offset(index)?;
+        // slots are always written whole, unset bytes are zeroed
+        // and left for bincode to reject
+        self.vm.try_read_into(offset, &mut self.buffer)?;
+        Ok(bincode::deserialize(&self.buffer)?)
+    }
+
+    // replace an existing element
+    pub fn set(&mut self, index: usize, element: &T) -> Result<()> {
+        self.slot_offset(index)?;
+        self.write_slot(index, element)
+    }
+
+    // elements in index order, stops after the first error
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<T>> + '_ {
+        let mut index = 0;
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed || index >= self.len {
+                return None;
+            }
+            let element = self.get(index);
+            failed = element.is_err();
+            index += 1;
+            Some(element)
+        })
+    }
+
+    // write every modified page and the length to the swap source
+    pub fn flush(&mut self) -> Result<()> {
+        self.vm.flush()
+    }
+
+    fn slot_offset(&self, index: usize) -> Result<usize> {
+        if index >= self.len {
+            return Err(Error::IndexOutOfRange { index });
+        }
+        Ok(META_SIZE + index * self.element_size)
+    }
+
+    fn write_slot(&mut self, index: usize, element: &T) -> Result<()> {
+        let offset = index
+            .checked_mul(self.element_size)
+            .and_then(|offset| offset.checked_add(META_SIZE))
+            .ok_or(Error::IndexOutOfRange { index })?;
+
+        let size = bincode::serialized_size(element)? as usize;
+        if size > self.element_size {
+            return Err(Error::ElementTooLarge {
+                size,
+                limit: self.element_size,
+            });
+        }
+
+        self.buffer.fill(0);
+        bincode::serialize_into(&mut self.buffer[..size], element)?;
+        self.vm.try_write_slice(offset, &self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VArray;
+    use crate::Error;
+    use serde::{Deserialize, Serialize};
+    use std::io::Cursor;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Record {
+        name: String,
+        value: f64,
+    }
+
+    fn record(i: usize) -> Record {
+        Record {
+            name: format!("record {}", i),
+            value: i as f64 * 0.5,
+        }
+    }
+
+    #[test]
+    fn push_get_set() {
+        let mut array = VArray::new(Cursor::new(Vec::new()), 40, 3).unwrap();
+        for i in 0..500 {
+            array.push(&record(i)).unwrap();
+        }
+        assert_eq!(array.len(), 500);
+
+        array.set(7, &record(1000)).unwrap();
+        assert_eq!(array.get(7).unwrap(), record(1000));
+        assert_eq!(array.get(499).unwrap(), record(499));
+        assert!(matches!(
+            array.get(500),
+            Err(Error::IndexOutOfRange { index: 500 })
+        ));
+        assert!(matches!(
+            array.set(500, &record(0)),
+            Err(Error::IndexOutOfRange { index: 500 })
+        ));
+    }
+
+    #[test]
+    fn element_too_large() {
+        let mut array = VArray::new(Cursor::new(Vec::new()), 8, 3).unwrap();
+        assert!(matches!(
+            array.push(&String::from("too long to fit")),
+            Err(Error::ElementTooLarge { size: 23, limit: 8 })
+        ));
+        assert!(array.is_empty());
+    }
+
+    #[test]
+    fn iter() {
+        let mut array = VArray::new(Cursor::new(Vec::new()), 8, 3).unwrap();
+        for i in 0..1000u64 {
+            array.push(&(i * i)).unwrap();
+        }
+        let squares: Vec<u64> = array.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(squares, (0..1000u64).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reopen() {
+        let mut swap_source = Cursor::new(Vec::new());
+        {
+            let mut array = VArray::new(&mut swap_source, 40, 3).unwrap();
+            for i in 0..100 {
+                array.push(&record(i)).unwrap();
+            }
+        }
+
+        let mut array = VArray::<Record, _>::open(&mut swap_source, 40, 3).unwrap();
+        assert_eq!(array.len(), 100);
+        assert_eq!(array.get(42).unwrap(), record(42));
+        drop(array);
+
+        assert!(matches!(
+            VArray::<Record, _>::open(&mut swap_source, 48, 3),
+            Err(Error::InvalidConfig(_))
+        ));
+        // 100 elements of 40 bytes are also 200 of 20
+        assert!(matches!(
+            VArray::<Record, _>::open(&mut swap_source, 20, 3),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
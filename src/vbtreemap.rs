@@ -0,0 +1,761 @@
+use crate::error::{Error, Result};
+use crate::page_store::PageStore;
+use crate::virtual_memory::VirtualMemory;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+const SIGNATURE: &[u8; 4] = b"VBT1";
+// signature, root page, length, pages allocated
+const META_SIZE: usize = SIGNATURE.len() + 3 * 8;
+// node kind, key count, next leaf or first child
+const NODE_HEADER: usize = 1 + 4 + 8;
+const LEAF: u8 = 0;
+const INTERNAL: u8 = 1;
+
+// Ordered map of serializable keys and values kept in virtual memory
+// as a B+tree with one node per page, so the page buffer caches nodes.
+// Values live in the leaves, leaves are linked in key order for ranges.
+//
+// Removal is lazy: entries leave their leaf but nodes are never merged,
+// underfull and empty leaves stay in the tree.
+#[derive(Debug)]
+pub struct VBTreeMap<K, V, S>
+where
+    S: PageStore,
+    K: Serialize + DeserializeOwned + Ord,
+    V: Serialize + DeserializeOwned,
+{
+    vm: VirtualMemory<S>,
+    root: usize,
+    len: usize,
+    // pages allocated, page 0 holds the metadata
+    pages: usize,
+    // bytes in one node, the data size of a virtual memory page
+    page_size: usize,
+    entries: PhantomData<(K, V)>,
+}
+
+// key with its encoding, kept to write the node back
+#[derive(Debug)]
+struct Key<K> {
+    value: K,
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum Node<K> {
+    Leaf {
+        keys: Vec<Key<K>>,
+        values: Vec<Vec<u8>>,
+        next: Option<usize>,
+    },
+    // keys in `children[i + 1]` are greater or equal to `keys[i]`
+    Internal {
+        keys: Vec<Key<K>>,
+        children: Vec<usize>,
+    },
+}
+
+impl<K, V, S> VBTreeMap<K, V, S>
+where
+    S: PageStore,
+    K: Serialize + DeserializeOwned + Ord,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn new(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        let vm = VirtualMemory::try_new(swap_source, page_size, buffer_size)?;
+        // root starts as an empty leaf, a page never written reads as one
+        let mut tree = Self::with_vm(vm, 1, 0, 2)?;
+        tree.write_meta()?;
+        Ok(tree)
+    }
+
+    // reopen swap source created by `new`, keeping its entries
+    pub fn open(swap_source: S, buffer_size: usize) -> Result<Self> {
+        let mut vm = VirtualMemory::open(swap_source, buffer_size)?;
+        let mut bytes = [0u8; META_SIZE];
+        vm.try_read_into(0, &mut bytes)?;
+        if &bytes[..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::InvalidConfig("Swap source doesn't hold a VBTreeMap"));
+        }
+
+        let field = |i: usize| {
+            let offset = SIGNATURE.len() + i * 8;
+            u64::from_le_bytes(read(&bytes, offset)) as usize
+        };
+        Self::with_vm(vm, field(0), field(1), field(2))
+    }
+
+    fn with_vm(vm: VirtualMemory<S>, root: usize, len: usize, pages: usize) -> Result<Self> {
+        let page_size = vm.data_size();
+        if page_size < 64 {
+            return Err(Error::InvalidConfig(
+                "VBTreeMap page is too small to hold a node",
+            ));
+        }
+
+        Ok(VBTreeMap {
+            vm,
+            root,
+            len,
+            pages,
+            page_size,
+            entries: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // returns the value previously stored under `key`
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<Option<V>> {
+        let key_bytes = bincode::serialize(key)?;
+        let value = bincode::serialize(value)?;
+        // a full node split in halves must give two nodes that fit,
+        // also when the key moves up to an internal node
+        let limit = (self.page_size - NODE_HEADER) / 3 - 8;
+        let size = leaf_entry_size(&key_bytes, &value);
+        if size > limit {
+            return Err(Error::ElementTooLarge { size, limit });
+        }
+
+        let path = self.find_leaf(Bound::Included(key))?;
+        let (page, _) = *path.last().expect("Path always ends in a leaf");
+        let mut node = self.read_node(page)?;
+        let old = match &mut node {
+            Node::Leaf { keys, values, .. } => match keys.binary_search_by(|k| k.value.cmp(key)) {
+                Ok(i) => Some(std::mem::replace(&mut values[i], value)),
+                Err(i) => {
+                    let key = bincode::deserialize(&key_bytes)?;
+                    keys.insert(
+                        i,
+                        Key {
+                            value: key,
+                            bytes: key_bytes,
+                        },
+                    );
+                    values.insert(i, value);
+                    None
+                }
+            },
+            Node::Internal { .. } => return Err(Error::CorruptPage { index: page }),
+        };
+
+        self.write_split(path, node)?;
+        if old.is_none() {
+            self.len += 1;
+        }
+        match old {
+            Some(old) => Ok(Some(bincode::deserialize(&old)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+        let path = self.find_leaf(Bound::Included(key))?;
+        let (page, _) = *path.last().expect("Path always ends in a leaf");
+        match self.read_node(page)? {
+            Node::Leaf { keys, values, .. } => match keys.binary_search_by(|k| k.value.cmp(key)) {
+                Ok(i) => Ok(Some(bincode::deserialize(&values[i])?)),
+                Err(_) => Ok(None),
+            },
+            Node::Internal { .. } => Err(Error::CorruptPage { index: page }),
+        }
+    }
+
+    pub fn contains_key(&mut self, key: &K) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    // returns the removed value
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>> {
+        let path = self.find_leaf(Bound::Included(key))?;
+        let (page, _) = *path.last().expect("Path always ends in a leaf");
+        let mut node = self.read_node(page)?;
+        let value = match &mut node {
+            Node::Leaf { keys, values, .. } => match keys.binary_search_by(|k| k.value.cmp(key)) {
+                Ok(i) => {
+                    keys.remove(i);
+                    values.remove(i)
+                }
+                Err(_) => return Ok(None),
+            },
+            Node::Internal { .. } => return Err(Error::CorruptPage { index: page }),
+        };
+
+        self.write_node(page, &node)?;
+        self.len -= 1;
+        Ok(Some(bincode::deserialize(&value)?))
+    }
+
+    // entries with keys in `range` in ascending order,
+    // stops after the first error
+    pub fn range<'a, R>(&'a mut self, range: R) -> impl Iterator<Item = Result<(K, V)>> + 'a
+    where
+        R: RangeBounds<K> + 'a,
+    {
+        // entries of the current leaf in reverse, to pop them in order
+        let mut entries: Vec<(Key<K>, Vec<u8>)> = Vec::new();
+        let mut next = None;
+        let mut started = false;
+        let mut done = false;
+
+        std::iter::from_fn(move || loop {
+            if done {
+                return None;
+            }
+
+            let leaf = if !started {
+                started = true;
+                match self.find_leaf(range.start_bound()) {
+                    Ok(path) => Ok(path.last().expect("Path always ends in a leaf").0),
+                    Err(error) => Err(error),
+                }
+            } else if let Some((key, value)) = entries.pop() {
+                let before_start = match range.start_bound() {
+                    Bound::Included(start) => key.value < *start,
+                    Bound::Excluded(start) => key.value <= *start,
+                    Bound::Unbounded => false,
+                };
+                if before_start {
+                    continue;
+                }
+                if !range.contains(&key.value) {
+                    done = true;
+                    return None;
+                }
+
+                let value = bincode::deserialize(&value).map_err(Error::from);
+                done = value.is_err();
+                return Some(value.map(|value| (key.value, value)));
+            } else {
+                match next {
+                    Some(page) => Ok(page),
+                    None => {
+                        done = true;
+                        return None;
+                    }
+                }
+            };
+
+            let node = leaf.and_then(|page| Ok((page, self.read_node(page)?)));
+            match node {
+                Ok((
+                    _,
+                    Node::Leaf {
+                        keys,
+                        values,
+                        next: leaf_next,
+                    },
+                )) => {
+                    entries = keys.into_iter().zip(values).rev().collect();
+                    next = leaf_next;
+                }
+                // leaves only link to leaves
+                Ok((page, Node::Internal { .. })) => {
+                    done = true;
+                    return Some(Err(Error::CorruptPage { index: page }));
+                }
+                Err(error) => {
+                    done = true;
+                    return Some(Err(error));
+                }
+            }
+        })
+    }
+
+    // every entry in ascending key order
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        self.range(..)
+    }
+
+    // write every modified page and the tree metadata to the swap source
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_meta()?;
+        self.vm.flush()
+    }
+
+    fn write_meta(&mut self) -> Result<()> {
+        let mut bytes = Vec::with_capacity(META_SIZE);
+        bytes.extend_from_slice(SIGNATURE);
+        for field in [self.root, self.len, self.pages] {
+            bytes.extend_from_slice(&(field as u64).to_le_bytes());
+        }
+        self.vm.try_write_slice(0, &bytes)
+    }
+
+    // pages from the root to the leaf that holds `bound`,
+    // with the child taken in each of them
+    fn find_leaf(&mut self, bound: Bound<&K>) -> Result<Vec<(usize, usize)>> {
+        let mut path = Vec::new();
+        let mut page = self.root;
+        loop {
+            if path.len() > self.pages {
+                // deeper than every page, the tree loops
+                return Err(Error::CorruptPage { index: page });
+            }
+
+            match self.read_node(page)? {
+                Node::Leaf { .. } => {
+                    path.push((page, 0));
+                    return Ok(path);
+                }
+                Node::Internal { keys, children } => {
+                    let child = match bound {
+                        Bound::Included(key) | Bound::Excluded(key) => {
+                            keys.partition_point(|k| k.value <= *key)
+                        }
+                        Bound::Unbounded => 0,
+                    };
+                    path.push((page, child));
+                    page = children[child];
+                }
+            }
+        }
+    }
+
+    // write `node` at the end of `path`, splitting it and its parents
+    // as long as they don't fit in a page. The root and the pages
+    // allocated change only once every node is written.
+    fn write_split(&mut self, mut path: Vec<(usize, usize)>, mut node: Node<K>) -> Result<()> {
+        let mut root = self.root;
+        let mut pages = self.pages;
+        // new right halves, then the node that took the last separator,
+        // then the split nodes from the top down: every prefix of these
+        // writes leaves a tree that finds each entry
+        let mut new_nodes = Vec::new();
+        let mut split_nodes = Vec::new();
+        while let Some((page, _)) = path.pop() {
+            if node.size() <= self.page_size {
+                new_nodes.push((page, node));
+                break;
+            }
+
+            let right_page = pages;
+            pages += 1;
+            let (separator, right) = node.split(page, right_page)?;
+            new_nodes.push((right_page, right));
+
+            let parent = match path.last() {
+                Some(&(parent, child)) => {
+                    let mut parent_node = self.read_node(parent)?;
+                    match &mut parent_node {
+                        Node::Internal { keys, children } => {
+                            keys.insert(child, separator);
+                            children.insert(child + 1, right_page);
+                        }
+                        Node::Leaf { .. } => return Err(Error::CorruptPage { index: parent }),
+                    }
+                    parent_node
+                }
+                None => {
+                    // root was split, the tree grows by one level
+                    root = pages;
+                    pages += 1;
+                    path.push((root, 0));
+                    Node::Internal {
+                        keys: vec![separator],
+                        children: vec![page, right_page],
+                    }
+                }
+            };
+            split_nodes.push((page, std::mem::replace(&mut node, parent)));
+        }
+
+        for (page, node) in new_nodes.iter().chain(split_nodes.iter().rev()) {
+            self.write_node(*page, node)?;
+        }
+        self.root = root;
+        self.pages = pages;
+        Ok(())
+    }
+
+    fn read_node(&mut self, page: usize) -> Result<Node<K>> {
+        let mut bytes = vec![0u8; self.page_size];
+        self.vm.try_read_into(page * self.page_size, &mut bytes)?;
+        Node::decode(&bytes, page)
+    }
+
+    fn write_node(&mut self, page: usize, node: &Node<K>) -> Result<()> {
+        let mut bytes = node.encode();
+        bytes.resize(self.page_size, 0);
+        self.vm.try_write_slice(page * self.page_size, &bytes)
+    }
+}
+
+impl<K, V, S> Drop for VBTreeMap<K, V, S>
+where
+    S: PageStore,
+    K: Serialize + DeserializeOwned + Ord,
+    V: Serialize + DeserializeOwned,
+{
+    fn drop(&mut self) {
+        // virtual memory writes the pages back when it is dropped next
+        let _ = self.write_meta();
+    }
+}
+
+impl<K> Node<K>
+where
+    K: DeserializeOwned,
+{
+    fn size(&self) -> usize {
+        match self {
+            Node::Leaf { keys, values, .. } => {
+                let entries = keys.iter().zip(values);
+                NODE_HEADER
+                    + entries
+                        .map(|(k, v)| leaf_entry_size(&k.bytes, v))
+                        .sum::<usize>()
+            }
+            Node::Internal { keys, .. } => {
+                NODE_HEADER
+                    + keys
+                        .iter()
+                        .map(|k| internal_entry_size(&k.bytes))
+                        .sum::<usize>()
+            }
+        }
+    }
+
+    // move the upper half of the entries of the node in `page`, by size,
+    // to a new node stored in `right_page`, returns the separator for the
+    // parent. A node too large for its page holds at least two entries,
+    // unless it was read corrupt.
+    fn split(&mut self, page: usize, right_page: usize) -> Result<(Key<K>, Node<K>)> {
+        match self {
+            Node::Leaf { keys, .. } if keys.len() < 2 => Err(Error::CorruptPage { index: page }),
+            // both halves keep a key once the separator moves up
+            Node::Internal { keys, .. } if keys.len() < 3 => {
+                Err(Error::CorruptPage { index: page })
+            }
+            Node::Leaf { keys, values, next } => {
+                let sizes = keys.iter().zip(values.iter());
+                let sizes: Vec<usize> = sizes.map(|(k, v)| leaf_entry_size(&k.bytes, v)).collect();
+                let at = split_point(&sizes).clamp(1, keys.len() - 1);
+
+                let right_keys = keys.split_off(at);
+                let separator = Key {
+                    value: bincode::deserialize(&right_keys[0].bytes)?,
+                    bytes: right_keys[0].bytes.clone(),
+                };
+                let right = Node::Leaf {
+                    keys: right_keys,
+                    values: values.split_off(at),
+                    next: next.replace(right_page),
+                };
+                Ok((separator, right))
+            }
+            Node::Internal { keys, children } => {
+                let sizes: Vec<usize> =
+                    keys.iter().map(|k| internal_entry_size(&k.bytes)).collect();
+                let at = split_point(&sizes).clamp(1, keys.len() - 2);
+
+                // the key at the split point moves up to the parent
+                let mut right_keys = keys.split_off(at);
+                let separator = right_keys.remove(0);
+                let right = Node::Internal {
+                    keys: right_keys,
+                    children: children.split_off(at + 1),
+                };
+                Ok((separator, right))
+            }
+        }
+    }
+
+    // Node layout, little-endian:
+    //
+    // leaf:     kind 0, count u32, next leaf as page + 1 or 0 u64,
+    //           then key size u32, value size u32, key, value per entry
+    // internal: kind 1, count u32, first child u64,
+    //           then key size u32, key, child u64 per key
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        match self {
+            Node::Leaf { keys, values, next } => {
+                bytes.push(LEAF);
+                bytes.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+                let next = next.map_or(0, |page| page as u64 + 1);
+                bytes.extend_from_slice(&next.to_le_bytes());
+                for (key, value) in keys.iter().zip(values) {
+                    bytes.extend_from_slice(&(key.bytes.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(&key.bytes);
+                    bytes.extend_from_slice(value);
+                }
+            }
+            Node::Internal { keys, children } => {
+                bytes.push(INTERNAL);
+                bytes.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(&(children[0] as u64).to_le_bytes());
+                for (key, &child) in keys.iter().zip(&children[1..]) {
+                    bytes.extend_from_slice(&(key.bytes.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(&key.bytes);
+                    bytes.extend_from_slice(&(child as u64).to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8], page: usize) -> Result<Self> {
+        let mut reader = Reader {
+            bytes,
+            offset: 0,
+            page,
+        };
+        let kind = reader.take(1)?[0];
+        let count = u32::from_le_bytes(reader.array()?) as usize;
+        let first = u64::from_le_bytes(reader.array()?);
+
+        let mut keys = Vec::with_capacity(count);
+        let mut key = |reader: &mut Reader, len: usize| -> Result<()> {
+            let bytes = reader.take(len)?.to_vec();
+            keys.push(Key {
+                value: bincode::deserialize(&bytes)?,
+                bytes,
+            });
+            Ok(())
+        };
+
+        match kind {
+            LEAF => {
+                let mut values = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key_len = u32::from_le_bytes(reader.array()?) as usize;
+                    let value_len = u32::from_le_bytes(reader.array()?) as usize;
+                    key(&mut reader, key_len)?;
+                    values.push(reader.take(value_len)?.to_vec());
+                }
+                let next = first.checked_sub(1).map(|page| page as usize);
+                Ok(Node::Leaf { keys, values, next })
+            }
+            INTERNAL => {
+                let mut children = vec![first as usize];
+                for _ in 0..count {
+                    let key_len = u32::from_le_bytes(reader.array()?) as usize;
+                    key(&mut reader, key_len)?;
+                    children.push(u64::from_le_bytes(reader.array()?) as usize);
+                }
+                Ok(Node::Internal { keys, children })
+            }
+            _ => Err(Error::CorruptPage { index: page }),
+        }
+    }
+}
+
+// reads node fields, running past the page means it is corrupt
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    page: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.offset..end))
+            .ok_or(Error::CorruptPage { index: self.page })?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("Took exactly N bytes"))
+    }
+}
+
+fn leaf_entry_size(key: &[u8], value: &[u8]) -> usize {
+    4 + 4 + key.len() + value.len()
+}
+
+fn internal_entry_size(key: &[u8]) -> usize {
+    4 + key.len() + 8
+}
+
+// number of entries whose sizes add up to at least half of the total
+fn split_point(sizes: &[usize]) -> usize {
+    let half = sizes.iter().sum::<usize>() / 2;
+    let mut total = 0;
+    sizes
+        .iter()
+        .position(|size| {
+            total += size;
+            total >= half
+        })
+        .map_or(sizes.len(), |i| i + 1)
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N]
+        .try_into()
+        .expect("Field out of bounds")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Key, Node, VBTreeMap};
+    use crate::faulty_store::{Fault, FaultyStore};
+    use crate::Error;
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    // keys in a scrambled order
+    fn key(i: u64) -> u64 {
+        i * 7919 % 10_007
+    }
+
+    #[test]
+    fn split_too_few_keys() {
+        let key = |value: u64| Key {
+            value,
+            bytes: bincode::serialize(&value).unwrap(),
+        };
+        // as read from a corrupt page, it can't be split
+        let mut node = Node::Internal {
+            keys: vec![key(1), key(2)],
+            children: vec![3, 4, 5],
+        };
+        assert!(matches!(
+            node.split(7, 8),
+            Err(Error::CorruptPage { index: 7 })
+        ));
+        let mut node = Node::Leaf {
+            keys: vec![key(1)],
+            values: vec![vec![0; 4]],
+            next: None,
+        };
+        assert!(matches!(
+            node.split(7, 8),
+            Err(Error::CorruptPage { index: 7 })
+        ));
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut tree = VBTreeMap::new(Cursor::new(Vec::new()), 256, 4).unwrap();
+        let mut expected = BTreeMap::new();
+        for i in 0..5000 {
+            let value = format!("value {}", i);
+            assert_eq!(tree.insert(&key(i), &value).unwrap(), None);
+            expected.insert(key(i), value);
+        }
+        assert_eq!(
+            tree.insert(&key(9), &String::from("nine")).unwrap(),
+            Some(String::from("value 9"))
+        );
+        expected.insert(key(9), String::from("nine"));
+
+        for i in (0..5000).step_by(4) {
+            assert_eq!(tree.remove(&key(i)).unwrap(), expected.remove(&key(i)));
+        }
+        assert_eq!(tree.remove(&key(0)).unwrap(), None);
+        assert_eq!(tree.len(), expected.len());
+        // the root was split at least once
+        assert_ne!(tree.root, 1);
+
+        for i in 0..5000 {
+            assert_eq!(tree.get(&key(i)).unwrap(), expected.get(&key(i)).cloned());
+        }
+        let entries: Vec<(u64, String)> = tree.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn range() {
+        let mut tree = VBTreeMap::new(Cursor::new(Vec::new()), 256, 4).unwrap();
+        let mut expected = BTreeMap::new();
+        for i in 0..2000 {
+            tree.insert(&key(i), &i).unwrap();
+            expected.insert(key(i), i);
+        }
+        // lazily removed entries leave empty leaves to skip
+        for i in 0..2000 {
+            if (3000..6000).contains(&key(i)) {
+                tree.remove(&key(i)).unwrap();
+                expected.remove(&key(i));
+            }
+        }
+
+        let ranges = [(0, 10), (2500, 6500), (4000, 5000), (9990, 20_000)];
+        for (start, end) in ranges {
+            let entries: Vec<(u64, u64)> = tree.range(start..end).map(|e| e.unwrap()).collect();
+            let expected: Vec<(u64, u64)> =
+                expected.range(start..end).map(|(&k, &v)| (k, v)).collect();
+            assert_eq!(entries, expected);
+        }
+
+        let tail: Vec<u64> = tree.range(9000..).map(|e| e.unwrap().0).collect();
+        assert_eq!(
+            tail,
+            expected.range(9000..).map(|(&k, _)| k).collect::<Vec<_>>()
+        );
+        let head: Vec<u64> = tree.range(..=100).map(|e| e.unwrap().0).collect();
+        assert_eq!(
+            head,
+            expected.range(..=100).map(|(&k, _)| k).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reopen() {
+        let mut swap_source = Cursor::new(Vec::new());
+        {
+            let mut tree = VBTreeMap::new(&mut swap_source, 256, 4).unwrap();
+            for i in 0..1000 {
+                tree.insert(&format!("{:04}", key(i)), &i).unwrap();
+            }
+        }
+
+        let mut tree = VBTreeMap::<String, u64, _>::open(&mut swap_source, 4).unwrap();
+        assert_eq!(tree.len(), 1000);
+        assert_eq!(tree.get(&format!("{:04}", key(77))).unwrap(), Some(77));
+        let first = tree.iter().next().unwrap().unwrap();
+        assert_eq!(first, (String::from("0000"), 0));
+    }
+
+    #[test]
+    fn failed_split_keeps_the_tree() {
+        // three pages of buffer, splitting the root leaf needs a fourth
+        let store = FaultyStore::new(Cursor::new(Vec::new()));
+        let mut tree = VBTreeMap::new(store, 256, 3).unwrap();
+        let writes = tree.vm.swap_source.writes();
+        tree.vm.swap_source.fail_write(writes, Fault::Seek);
+
+        let mut inserted = 0;
+        while tree.insert(&key(inserted), &inserted).is_ok() {
+            inserted += 1;
+        }
+        assert_eq!(tree.len(), inserted as usize);
+        assert_eq!((tree.root, tree.pages), (1, 2));
+
+        for i in inserted..inserted + 100 {
+            tree.insert(&key(i), &i).unwrap();
+        }
+        assert_eq!(tree.len(), inserted as usize + 100);
+        for i in 0..inserted + 100 {
+            assert_eq!(tree.get(&key(i)).unwrap(), Some(i));
+        }
+        let keys: Vec<u64> = tree.iter().map(|e| e.unwrap().0).collect();
+        assert_eq!(keys.len(), tree.len());
+    }
+
+    #[test]
+    fn entry_too_large() {
+        let mut tree = VBTreeMap::new(Cursor::new(Vec::new()), 256, 4).unwrap();
+        assert!(matches!(
+            tree.insert(&1u8, &vec![0u8; 200]),
+            Err(Error::ElementTooLarge { .. })
+        ));
+        assert!(tree.is_empty());
+    }
+}
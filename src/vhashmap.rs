@@ -0,0 +1,705 @@
+use crate::div_ceil;
+use crate::error::{Error, Result};
+use crate::page_store::PageStore;
+use crate::virtual_memory::VirtualMemory;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+const SIGNATURE: &[u8; 4] = b"VHM1";
+// bucket groups, group `g > 0` holds buckets 2^(g-1)..2^g
+const GROUPS: usize = 65;
+const META_SIZE: usize = SIGNATURE.len() + 7 * 8 + GROUPS * 8;
+// entry count and next page of the chain, page index + 1 or 0
+const PAGE_HEADER: usize = 4 + 8;
+// key and value length
+const ENTRY_HEADER: usize = 4 + 4;
+// buckets are split once entries fill this percentage of them
+const MAX_LOAD: usize = 75;
+
+// Map of serializable keys and values kept in virtual memory, built with
+// linear hashing: every bucket is a chain of pages that grows one page
+// at a time, and the table grows by splitting one bucket at a time,
+// so only the buckets in use have to stay in the page buffer.
+//
+// Keys are compared and hashed by their bincode encoding.
+//
+// Pages are laid out as in Berkeley DB hash files: metadata first,
+// then bucket groups, each followed by the overflow pages allocated
+// while it was the last group.
+#[derive(Debug)]
+pub struct VHashMap<K, V, S>
+where
+    S: PageStore,
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    vm: VirtualMemory<S>,
+    meta: Meta,
+    // bytes in one page of the map, the data size of a virtual memory page
+    page_size: usize,
+    meta_pages: usize,
+    entries: PhantomData<(K, V)>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Meta {
+    len: u64,
+    // bytes taken by entries in all pages
+    used: u64,
+    max_bucket: u64,
+    low_mask: u64,
+    high_mask: u64,
+    // overflow pages ever allocated
+    overflow_pages: u64,
+    // first free overflow page, page index + 1 or 0
+    free_head: u64,
+    // overflow pages allocated before each bucket group
+    spares: Vec<u64>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        ENTRY_HEADER + self.key.len() + self.value.len()
+    }
+}
+
+impl<K, V, S> VHashMap<K, V, S>
+where
+    S: PageStore,
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn new(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        let vm = VirtualMemory::try_new(swap_source, page_size, buffer_size)?;
+        let meta = Meta {
+            high_mask: 1,
+            spares: vec![0; GROUPS],
+            ..Meta::default()
+        };
+        let mut map = Self::with_vm(vm, meta)?;
+        map.write_meta()?;
+        Ok(map)
+    }
+
+    // reopen swap source created by `new`, keeping its entries
+    pub fn open(swap_source: S, buffer_size: usize) -> Result<Self> {
+        let mut vm = VirtualMemory::open(swap_source, buffer_size)?;
+        let mut bytes = vec![0u8; META_SIZE];
+        vm.try_read_into(0, &mut bytes)?;
+        let meta = Meta::decode(&bytes)?;
+        let map = Self::with_vm(vm, meta)?;
+        map.meta.check(map.meta_pages, map.page_size)?;
+        Ok(map)
+    }
+
+    fn with_vm(vm: VirtualMemory<S>, meta: Meta) -> Result<Self> {
+        let page_size = vm.data_size();
+        if page_size < PAGE_HEADER + ENTRY_HEADER {
+            return Err(Error::InvalidConfig(
+                "VHashMap page is too small to hold an entry",
+            ));
+        }
+
+        Ok(VHashMap {
+            vm,
+            meta,
+            page_size,
+            meta_pages: div_ceil(META_SIZE, page_size),
+            entries: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.meta.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meta.len == 0
+    }
+
+    // returns the value previously stored under `key`
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<Option<V>> {
+        let entry = Entry {
+            key: bincode::serialize(key)?,
+            value: bincode::serialize(value)?,
+        };
+        let limit = self.page_size - PAGE_HEADER;
+        if entry.size() > limit {
+            return Err(Error::ElementTooLarge {
+                size: entry.size(),
+                limit,
+            });
+        }
+
+        // counters change only once the chain is written
+        let mut len = self.meta.len;
+        let mut used = self.meta.used + entry.size() as u64;
+        let bucket = self.bucket(&entry.key);
+        let (pages, mut entries) = self.read_chain(bucket)?;
+        let old = match entries.iter().position(|e| e.key == entry.key) {
+            Some(position) => {
+                let old = std::mem::replace(&mut entries[position], entry);
+                used -= old.size() as u64;
+                Some(old)
+            }
+            None => {
+                entries.push(entry);
+                len += 1;
+                None
+            }
+        };
+        self.write_chain(pages, &entries, self.meta.max_bucket)?;
+        self.meta.len = len;
+        self.meta.used = used;
+
+        if self.meta.used * 100 > self.capacity() * MAX_LOAD as u64 {
+            self.split()?;
+        }
+        match old {
+            Some(old) => Ok(Some(bincode::deserialize(&old.value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+        let key = bincode::serialize(key)?;
+        let (_, entries) = self.read_chain(self.bucket(&key))?;
+        match entries.into_iter().find(|e| e.key == key) {
+            Some(entry) => Ok(Some(bincode::deserialize(&entry.value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains_key(&mut self, key: &K) -> Result<bool> {
+        let key = bincode::serialize(key)?;
+        let (_, entries) = self.read_chain(self.bucket(&key))?;
+        Ok(entries.iter().any(|e| e.key == key))
+    }
+
+    // returns the removed value
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>> {
+        let key = bincode::serialize(key)?;
+        let (pages, mut entries) = self.read_chain(self.bucket(&key))?;
+        let position = match entries.iter().position(|e| e.key == key) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+
+        let entry = entries.swap_remove(position);
+        self.write_chain(pages, &entries, self.meta.max_bucket)?;
+        self.meta.used -= entry.size() as u64;
+        self.meta.len -= 1;
+        Ok(Some(bincode::deserialize(&entry.value)?))
+    }
+
+    // entries in bucket order, stops after the first error
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        let mut bucket = 0;
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut failed = false;
+        std::iter::from_fn(move || loop {
+            if failed {
+                return None;
+            }
+            if let Some(entry) = entries.pop() {
+                let decoded = bincode::deserialize(&entry.key)
+                    .and_then(|key| Ok((key, bincode::deserialize(&entry.value)?)))
+                    .map_err(Error::from);
+                failed = decoded.is_err();
+                return Some(decoded);
+            }
+            if bucket > self.meta.max_bucket {
+                return None;
+            }
+
+            match self.read_chain(bucket) {
+                Ok((_, chain)) => entries = chain,
+                Err(error) => {
+                    failed = true;
+                    return Some(Err(error));
+                }
+            }
+            bucket += 1;
+        })
+    }
+
+    // write every modified page and the map metadata to the swap source
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_meta()?;
+        self.vm.flush()
+    }
+
+    fn write_meta(&mut self) -> Result<()> {
+        self.vm.try_write_slice(0, &self.meta.encode())
+    }
+
+    // bytes that entries can take in all buckets, overflow pages not counted
+    fn capacity(&self) -> u64 {
+        (self.meta.max_bucket + 1) * (self.page_size - PAGE_HEADER) as u64
+    }
+
+    fn bucket(&self, key: &[u8]) -> u64 {
+        self.meta.bucket(key)
+    }
+
+    fn bucket_page(&self, bucket: u64) -> usize {
+        self.meta.bucket_page(bucket, self.meta_pages)
+    }
+
+    // add bucket `max_bucket + 1` and move to it the entries
+    // of the bucket that shared its hashes so far
+    fn split(&mut self) -> Result<()> {
+        // the split is worked out on a copy, the map keeps its buckets
+        // until both chains are written
+        let mut meta = self.meta.clone();
+        let new = meta.max_bucket + 1;
+        if new > meta.high_mask {
+            meta.low_mask = meta.high_mask;
+            meta.high_mask = new | meta.low_mask;
+        }
+        if new.is_power_of_two() {
+            // first bucket of a group, its pages come after every
+            // overflow page allocated so far
+            meta.spares[group(new)] = meta.overflow_pages;
+        }
+        let old = new & meta.low_mask;
+        meta.max_bucket = new;
+
+        let (pages, entries) = self.read_chain(old)?;
+        let (moved, kept): (Vec<Entry>, Vec<Entry>) = entries
+            .into_iter()
+            .partition(|e| meta.bucket(&e.key) == new);
+        // the old chain still holds the moved entries until it is rewritten
+        let new_page = meta.bucket_page(new, self.meta_pages);
+        self.write_chain(vec![new_page], &moved, new)?;
+        self.write_chain(pages, &kept, new)?;
+
+        // pages allocated and freed by the writes are already counted
+        meta.overflow_pages = self.meta.overflow_pages;
+        meta.free_head = self.meta.free_head;
+        self.meta = meta;
+        Ok(())
+    }
+
+    // pages of the bucket chain and every entry in them
+    fn read_chain(&mut self, bucket: u64) -> Result<(Vec<usize>, Vec<Entry>)> {
+        let mut pages = Vec::new();
+        let mut entries = Vec::new();
+        let mut next = Some(self.bucket_page(bucket));
+
+        while let Some(page) = next {
+            let bytes = self.read_page(page)?;
+            let count = u32::from_le_bytes(read(&bytes, 0)) as usize;
+            next = match u64::from_le_bytes(read(&bytes, 4)) {
+                0 => None,
+                next => Some(next as usize - 1),
+            };
+
+            let mut offset = PAGE_HEADER;
+            for _ in 0..count {
+                let entry =
+                    decode_entry(&bytes, offset).ok_or(Error::CorruptPage { index: page })?;
+                offset += entry.size();
+                entries.push(entry);
+            }
+
+            pages.push(page);
+            if pages.len() > self.meta.overflow_pages as usize + 1 {
+                // chain longer than every overflow page, it loops
+                return Err(Error::CorruptPage { index: page });
+            }
+        }
+        Ok((pages, entries))
+    }
+
+    // pack `entries` into the pages of a chain, starting with its first page,
+    // allocating and freeing overflow pages as needed, the pages left over
+    // are freed once the chain is written. Pages are allocated after the
+    // group of `max_bucket` and stay allocated if a write fails.
+    fn write_chain(
+        &mut self,
+        mut pages: Vec<usize>,
+        entries: &[Entry],
+        max_bucket: u64,
+    ) -> Result<()> {
+        let mut chunks: Vec<&[Entry]> = Vec::new();
+        let mut start = 0;
+        let mut used = PAGE_HEADER;
+        for (i, entry) in entries.iter().enumerate() {
+            if used + entry.size() > self.page_size {
+                chunks.push(&entries[start..i]);
+                start = i;
+                used = PAGE_HEADER;
+            }
+            used += entry.size();
+        }
+        chunks.push(&entries[start..]);
+
+        let unused = pages.split_off(chunks.len().min(pages.len()));
+        while pages.len() < chunks.len() {
+            let page = self.allocate_page(max_bucket)?;
+            pages.push(page);
+        }
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let next = pages.get(i + 1).map_or(0, |&page| page as u64 + 1);
+            let mut bytes = vec![0u8; self.page_size];
+            bytes[0..4].copy_from_slice(&(chunk.len() as u32).to_le_bytes());
+            bytes[4..12].copy_from_slice(&next.to_le_bytes());
+
+            let mut offset = PAGE_HEADER;
+            for entry in chunk.iter() {
+                encode_entry(&mut bytes[offset..], entry);
+                offset += entry.size();
+            }
+            self.write_page(pages[i], &bytes)?;
+        }
+
+        for page in unused {
+            self.free_page(page)?;
+        }
+        Ok(())
+    }
+
+    fn allocate_page(&mut self, max_bucket: u64) -> Result<usize> {
+        if self.meta.free_head != 0 {
+            let page = self.meta.free_head as usize - 1;
+            let bytes = self.read_page(page)?;
+            self.meta.free_head = u64::from_le_bytes(read(&bytes, 4));
+            return Ok(page);
+        }
+
+        // after every bucket of the last group and its overflow pages
+        let buckets = 1u64 << group(max_bucket);
+        let page = self.meta_pages + (buckets + self.meta.overflow_pages) as usize;
+        self.meta.overflow_pages += 1;
+        Ok(page)
+    }
+
+    fn free_page(&mut self, page: usize) -> Result<()> {
+        let mut bytes = vec![0u8; self.page_size];
+        bytes[4..12].copy_from_slice(&self.meta.free_head.to_le_bytes());
+        self.write_page(page, &bytes)?;
+        self.meta.free_head = page as u64 + 1;
+        Ok(())
+    }
+
+    fn read_page(&mut self, page: usize) -> Result<Vec<u8>> {
+        let mut bytes = vec![0u8; self.page_size];
+        // pages never written read as empty
+        self.vm.try_read_into(self.page_offset(page)?, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_page(&mut self, page: usize, bytes: &[u8]) -> Result<()> {
+        self.vm.try_write_slice(self.page_offset(page)?, bytes)
+    }
+
+    // pages come from the swap source, a corrupt one can point anywhere
+    fn page_offset(&self, page: usize) -> Result<usize> {
+        page.checked_mul(self.page_size)
+            .ok_or(Error::CorruptPage { index: page })
+    }
+}
+
+impl<K, V, S> Drop for VHashMap<K, V, S>
+where
+    S: PageStore,
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    fn drop(&mut self) {
+        // virtual memory writes the pages back when it is dropped next
+        let _ = self.write_meta();
+    }
+}
+
+impl Meta {
+    fn bucket(&self, key: &[u8]) -> u64 {
+        let hash = hash(key);
+        let bucket = hash & self.high_mask;
+        if bucket > self.max_bucket {
+            hash & self.low_mask
+        } else {
+            bucket
+        }
+    }
+
+    fn bucket_page(&self, bucket: u64, meta_pages: usize) -> usize {
+        let group = group(bucket);
+        meta_pages + (bucket + self.spares[group]) as usize
+    }
+
+    // pointers and masks of metadata read from the swap source
+    // stay inside the map, `decode` only checks the signature
+    fn check(&self, meta_pages: usize, page_size: usize) -> Result<()> {
+        let corrupt = || Error::CorruptPage { index: 0 };
+        // buckets of every group up to the last one
+        let buckets = 1u64
+            .checked_shl(group(self.max_bucket) as u32)
+            .ok_or_else(corrupt)?;
+        if self.high_mask != (buckets - 1).max(1) || self.low_mask != self.high_mask >> 1 {
+            return Err(corrupt());
+        }
+        if self.spares.iter().any(|&spare| spare > self.overflow_pages) {
+            return Err(corrupt());
+        }
+
+        // one past the last page of the map, its offset must be addressable
+        let end = buckets
+            .checked_add(self.overflow_pages)
+            .and_then(|pages| pages.checked_add(meta_pages as u64))
+            .ok_or_else(corrupt)?;
+        usize::try_from(end)
+            .ok()
+            .and_then(|end| end.checked_mul(page_size))
+            .ok_or_else(corrupt)?;
+        if self.free_head > end {
+            return Err(corrupt());
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(META_SIZE);
+        bytes.extend_from_slice(SIGNATURE);
+        for field in [
+            self.len,
+            self.used,
+            self.max_bucket,
+            self.low_mask,
+            self.high_mask,
+            self.overflow_pages,
+            self.free_head,
+        ]
+        .iter()
+        .chain(&self.spares)
+        {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if &bytes[..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::InvalidConfig("Swap source doesn't hold a VHashMap"));
+        }
+        let mut fields = bytes[SIGNATURE.len()..]
+            .chunks_exact(8)
+            .map(|field| u64::from_le_bytes(read(field, 0)));
+        let mut next = || fields.next().expect("VHashMap metadata is too short");
+
+        Ok(Meta {
+            len: next(),
+            used: next(),
+            max_bucket: next(),
+            low_mask: next(),
+            high_mask: next(),
+            overflow_pages: next(),
+            free_head: next(),
+            spares: (0..GROUPS).map(|_| next()).collect(),
+        })
+    }
+}
+
+// bucket group of `bucket`, number of significant bits
+fn group(bucket: u64) -> usize {
+    (u64::BITS - bucket.leading_zeros()) as usize
+}
+
+// FNV-1a with a final mix, stable across runs and platforms
+// unlike the hashers of std
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^ (hash >> 33)
+}
+
+fn encode_entry(bytes: &mut [u8], entry: &Entry) {
+    let key_end = ENTRY_HEADER + entry.key.len();
+    bytes[0..4].copy_from_slice(&(entry.key.len() as u32).to_le_bytes());
+    bytes[4..8].copy_from_slice(&(entry.value.len() as u32).to_le_bytes());
+    bytes[ENTRY_HEADER..key_end].copy_from_slice(&entry.key);
+    bytes[key_end..key_end + entry.value.len()].copy_from_slice(&entry.value);
+}
+
+// `None` if the entry doesn't fit in the page
+fn decode_entry(bytes: &[u8], offset: usize) -> Option<Entry> {
+    let header = bytes.get(offset..offset + ENTRY_HEADER)?;
+    let key_len = u32::from_le_bytes(read(header, 0)) as usize;
+    let value_len = u32::from_le_bytes(read(header, 4)) as usize;
+    let key_start = offset + ENTRY_HEADER;
+    let value_start = key_start.checked_add(key_len)?;
+
+    Some(Entry {
+        key: bytes.get(key_start..value_start)?.to_vec(),
+        value: bytes
+            .get(value_start..value_start.checked_add(value_len)?)?
+            .to_vec(),
+    })
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N]
+        .try_into()
+        .expect("Field out of bounds")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{group, VHashMap};
+    use crate::faulty_store::{Fault, FaultyStore};
+    use crate::Error;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    fn key(i: usize) -> String {
+        // keys of different lengths
+        format!("key {}", i * 7919 % 100_003).repeat(1 + i % 3)
+    }
+
+    #[test]
+    fn corrupt_meta() {
+        let mut swap_source = Cursor::new(Vec::new());
+        {
+            let mut map = VHashMap::<u32, u32, _>::new(&mut swap_source, 256, 4).unwrap();
+            map.insert(&1, &2).unwrap();
+            // overflow pages past the end of the address space
+            map.meta.overflow_pages = u64::MAX - 1;
+        }
+        assert!(matches!(
+            VHashMap::<u32, u32, _>::open(&mut swap_source, 4),
+            Err(Error::CorruptPage { index: 0 })
+        ));
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(group(0), 0);
+        assert_eq!(group(1), 1);
+        assert_eq!(group(3), 2);
+        assert_eq!(group(4), 3);
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = VHashMap::new(Cursor::new(Vec::new()), 256, 4).unwrap();
+        let mut expected = HashMap::new();
+        for i in 0..3000 {
+            assert_eq!(map.insert(&key(i), &(i as u64)).unwrap(), None);
+            expected.insert(key(i), i as u64);
+        }
+        assert_eq!(map.insert(&key(5), &42).unwrap(), Some(5));
+        expected.insert(key(5), 42);
+
+        for i in (0..3000).step_by(3) {
+            assert_eq!(map.remove(&key(i)).unwrap(), expected.remove(&key(i)));
+        }
+        assert_eq!(map.remove(&key(0)).unwrap(), None);
+        assert_eq!(map.len(), expected.len());
+
+        for i in 0..3000 {
+            assert_eq!(map.get(&key(i)).unwrap(), expected.get(&key(i)).copied());
+        }
+        let entries: HashMap<String, u64> = map.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn freed_pages_are_reused() {
+        let mut map = VHashMap::new(Cursor::new(Vec::new()), 128, 4).unwrap();
+        for i in 0..500u64 {
+            map.insert(&i, &[0u8; 8]).unwrap();
+        }
+        let pages = map.meta.overflow_pages;
+        assert!(pages > 0);
+
+        for round in 1..4 {
+            for i in 0..500u64 {
+                assert!(map.remove(&i).unwrap().is_some());
+            }
+            assert!(map.is_empty());
+            for i in 0..500u64 {
+                map.insert(&i, &[round; 8]).unwrap();
+            }
+        }
+        assert_eq!(map.meta.overflow_pages, pages);
+        assert_eq!(map.get(&77).unwrap(), Some([3; 8]));
+    }
+
+    #[test]
+    fn failed_split_keeps_the_map() {
+        let fill = || {
+            // a buffer this small writes a page back on almost every access
+            let store = FaultyStore::new(Cursor::new(Vec::new()));
+            let mut map = VHashMap::new(store, 128, 3).unwrap();
+            for i in 0..300 {
+                map.insert(&key(i), &(i as u64)).unwrap();
+            }
+            map
+        };
+
+        let mut failures = 0;
+        for write in 0.. {
+            let mut map = fill();
+            let meta = map.meta.clone();
+            let writes = map.vm.swap_source.writes();
+            map.vm.swap_source.fail_write(writes + write, Fault::Seek);
+            if map.split().is_ok() {
+                break;
+            }
+            failures += 1;
+
+            // the map is as before the split, and can still grow
+            assert_eq!(map.meta.max_bucket, meta.max_bucket);
+            assert_eq!(map.meta.spares, meta.spares);
+            for i in 0..300 {
+                assert_eq!(map.get(&key(i)).unwrap(), Some(i as u64));
+            }
+            for i in 300..600 {
+                map.insert(&key(i), &(i as u64)).unwrap();
+            }
+            let entries: HashMap<String, u64> = map.iter().map(|e| e.unwrap()).collect();
+            assert_eq!(entries.len(), 600);
+            assert_eq!(map.len(), 600);
+        }
+        assert!(failures > 1);
+    }
+
+    #[test]
+    fn reopen() {
+        let mut swap_source = Cursor::new(Vec::new());
+        {
+            let mut map = VHashMap::new(&mut swap_source, 256, 4).unwrap();
+            for i in 0..500 {
+                map.insert(&key(i), &vec![i; i % 5]).unwrap();
+            }
+        }
+
+        let mut map = VHashMap::<String, Vec<usize>, _>::open(&mut swap_source, 4).unwrap();
+        assert_eq!(map.len(), 500);
+        assert_eq!(map.get(&key(123)).unwrap(), Some(vec![123; 3]));
+    }
+
+    #[test]
+    fn entry_too_large() {
+        let mut map = VHashMap::new(Cursor::new(Vec::new()), 64, 4).unwrap();
+        assert!(matches!(
+            map.insert(&1u8, &vec![0u8; 64]),
+            Err(Error::ElementTooLarge { .. })
+        ));
+        assert!(map.is_empty());
+    }
+}
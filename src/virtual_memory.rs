@@ -1,164 +1,772 @@
-use crate::page::Page;
-use crate::BITS_IN_BYTE;
-use std::io::{Read, Seek, SeekFrom, Write};
+use crate::compression::Compression;
+use crate::cursor::{MemoryCursor, UnsetBytes};
+use crate::encryption::{KeyProvider, PageCipher};
+use crate::error::{Error, Result};
+use crate::header::Header;
+use crate::options::Options;
+use crate::page::{Page, PageLayout};
+use crate::page_store::{read_exact_page, write_all_page, PageStore};
+use crate::pod::Pod;
+use crate::policy::{Lru, ReplacementPolicy};
+use crate::slot_table::SlotTable;
+use crate::stats::Stats;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Range;
 
+// Values of type `T` addressed by index and paged through `swap_source`,
+// bytes by default. Any `PageStore` works as the swap source, wrap
+// other `Read + Write + Seek` sources in `RwsStore`
 #[derive(Debug)]
-pub struct VirtualMemory<RWS>
+pub struct VirtualMemory<S, T = u8>
 where
-    RWS: Read + Write + Seek,
+    S: PageStore,
+    T: Pod,
 {
-    swap_source: RWS,
-    buffer: Vec<Page>,
-    page_size: usize,
-    max_index: usize,
+    pub(crate) swap_source: S,
+    // fixed number of frames, `None` for a free frame
+    buffer: Vec<Option<Page>>,
+    // frame of every page in the buffer
+    page_table: HashMap<usize, usize>,
+    free_frames: Vec<usize>,
+    policy: Box<dyn ReplacementPolicy>,
+    header: Header,
+    // where compressed pages are, `None` without compression
+    slots: Option<SlotTable>,
+    // seals pages on their way to the swap source, `None` without encryption
+    cipher: Option<PageCipher>,
+    // logical time, advanced by every page access
+    clock: u64,
+    stats: Stats,
+    // load corrupt pages as empty instead of failing
+    quarantine: bool,
+    // pages found corrupt and loaded as empty, see `set_quarantine`
+    quarantined: Vec<usize>,
+    values: PhantomData<T>,
 }
 
-impl<RWS> VirtualMemory<RWS>
+impl<S> VirtualMemory<S>
 where
-    RWS: Read + Write + Seek,
+    S: PageStore,
 {
-    const SIGNATURE: &[u8; 2] = b"VM";
-    pub fn new(mut swap_source: RWS, page_size: usize, buffer_size: usize) -> Self {
-        assert!(
-            buffer_size > 2,
-            "Virtual memory should have buffer size > 2"
-        );
-        assert!(page_size > 1, "Virtual memory should have page size > 1");
+    pub fn new(swap_source: S, page_size: usize, buffer_size: usize) -> Self {
+        Self::try_new(swap_source, page_size, buffer_size).expect("Failed to create virtual memory")
+    }
+
+    pub fn try_new(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        Self::try_new_typed(swap_source, page_size, buffer_size)
+    }
+
+    // reopen swap source created by `new`, keeping its contents
+    pub fn open(swap_source: S, buffer_size: usize) -> Result<Self> {
+        Self::open_typed(swap_source, buffer_size)
+    }
+
+    pub fn cursor(&mut self, unset: UnsetBytes) -> MemoryCursor<'_, S> {
+        MemoryCursor::new(self, unset)
+    }
+}
+
+impl<S, T> VirtualMemory<S, T>
+where
+    S: PageStore,
+    T: Pod,
+{
+    // same as `try_new`, for values of any `Pod` type
+    pub fn try_new_typed(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        Self::try_new_with_options(swap_source, page_size, buffer_size, Options::default())
+    }
+
+    // same as `try_new_typed`, in the format chosen by `options`
+    pub fn try_new_with_options(
+        swap_source: S,
+        page_size: usize,
+        buffer_size: usize,
+        options: Options,
+    ) -> Result<Self> {
+        Self::create(swap_source, page_size, buffer_size, options, None)
+    }
+
+    // same as `try_new_with_options`, pages are encrypted with the key
+    // from `key` as chosen by `options.encryption`
+    pub fn try_new_with_key(
+        swap_source: S,
+        page_size: usize,
+        buffer_size: usize,
+        options: Options,
+        key: &dyn KeyProvider,
+    ) -> Result<Self> {
+        Self::create(swap_source, page_size, buffer_size, options, Some(key))
+    }
+
+    fn create(
+        swap_source: S,
+        page_size: usize,
+        buffer_size: usize,
+        options: Options,
+        key: Option<&dyn KeyProvider>,
+    ) -> Result<Self> {
+        let layout = PageLayout::for_values::<T>(page_size, options.checksum, options.encryption)?;
+        options.compression.check_available()?;
+        let cipher = options.encryption.cipher(key)?;
+        let mut header = Header::new(layout);
+        header.flags |= options.compression.flags();
+
+        let mut vm = Self::with_header(swap_source, header, buffer_size)?;
+        if options.compression != Compression::None {
+            vm.slots = Some(SlotTable::new(header.data_offset, page_size));
+        }
+        vm.cipher = cipher;
+        vm.write_header()?;
+        Ok(vm)
+    }
+
+    // same as `open`, for values of any `Pod` type
+    pub fn open_typed(swap_source: S, buffer_size: usize) -> Result<Self> {
+        Self::reopen(swap_source, buffer_size, None)
+    }
+
+    // same as `open_typed`, for swap sources encrypted with the key from `key`
+    pub fn open_with_key(
+        swap_source: S,
+        buffer_size: usize,
+        key: &dyn KeyProvider,
+    ) -> Result<Self> {
+        Self::reopen(swap_source, buffer_size, Some(key))
+    }
+
+    fn reopen(
+        mut swap_source: S,
+        buffer_size: usize,
+        key: Option<&dyn KeyProvider>,
+    ) -> Result<Self> {
+        if swap_source.len()? < Header::SIZE as u64 {
+            return Err(Error::MissingHeader);
+        }
+
+        let mut bytes = [0u8; Header::SIZE];
+        read_exact_page(&mut swap_source, 0, &mut bytes)?;
+
+        let header = Header::decode(&bytes)?;
+        header.check_values::<T>()?;
+
+        let compression = header.compression();
+        compression.check_available()?;
+        let cipher = header.layout.encryption.cipher(key)?;
+        let slots = match compression {
+            Compression::None => None,
+            _ => Some(SlotTable::open(
+                &mut swap_source,
+                header.data_offset,
+                header.layout.page_size,
+            )?),
+        };
+
+        let mut vm = Self::with_header(swap_source, header, buffer_size)?;
+        vm.slots = slots;
+        vm.cipher = cipher;
+        Ok(vm)
+    }
 
-        swap_source
-            .write(Self::SIGNATURE)
-            .expect("Failed to write signature to swap file");
+    fn with_header(swap_source: S, header: Header, buffer_size: usize) -> Result<Self> {
+        check_buffer_size(buffer_size)?;
 
-        let buffer: Vec<Page> = Vec::with_capacity(buffer_size);
+        let buffer: Vec<Option<Page>> = (0..buffer_size).map(|_| None).collect();
+        // lowest frames are handed out first
+        let free_frames: Vec<usize> = (0..buffer_size).rev().collect();
+        let mut policy = Box::new(Lru::new());
+        policy.reset(buffer_size);
 
-        VirtualMemory {
+        Ok(VirtualMemory {
             swap_source,
             buffer,
-            page_size,
-            max_index: 0,
+            page_table: HashMap::with_capacity(buffer_size),
+            free_frames,
+            policy,
+            header,
+            slots: None,
+            cipher: None,
+            clock: 0,
+            stats: Stats::default(),
+            quarantine: false,
+            quarantined: Vec::new(),
+            values: PhantomData,
+        })
+    }
+
+    // replace the page replacement policy, pages already
+    // in the buffer are handed to the new policy in frame order
+    pub fn set_policy<P>(&mut self, policy: P)
+    where
+        P: ReplacementPolicy + 'static,
+    {
+        self.policy = Box::new(policy);
+        self.policy.reset(self.buffer.len());
+        // oldest pages first, so the new policy sees the same history
+        let mut resident: Vec<(u64, usize)> = self
+            .buffer
+            .iter()
+            .enumerate()
+            .filter_map(|(frame, page)| page.as_ref().map(|page| (page.last_access, frame)))
+            .collect();
+        resident.sort_unstable();
+        for (last_access, frame) in resident {
+            self.policy.insert(frame, last_access);
         }
     }
 
-    pub fn write(&mut self, index: usize, element: u8) {
-        self.max_index = self.max_index.max(index);
+    // one past the highest index ever written
+    pub fn len(&self) -> usize {
+        self.header.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.len == 0
+    }
+
+    // length kept in the header for a container built on virtual
+    // memory, saved with the header
+    pub(crate) fn container_len(&self) -> usize {
+        self.header.container_len
+    }
+
+    pub(crate) fn set_container_len(&mut self, len: usize) {
+        self.header.container_len = len;
+    }
+
+    // page traffic since this value was created
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // with `quarantine`, a page failing its checksum is loaded empty and
+    // listed in `quarantined` instead of failing with `Error::CorruptPage`,
+    // its bytes in the swap source stay until the page is written again
+    pub fn set_quarantine(&mut self, quarantine: bool) {
+        self.quarantine = quarantine;
+    }
+
+    // indices of pages loaded empty because they were corrupt
+    pub fn quarantined(&self) -> &[usize] {
+        &self.quarantined
+    }
 
+    pub fn write(&mut self, index: usize, element: T) {
+        self.try_write(index, element)
+            .expect("Failed to write to virtual memory")
+    }
+
+    pub fn try_write(&mut self, index: usize, element: T) -> Result<()> {
         let page_index = index / self.data_size();
         let value_offset = index % self.data_size();
-        self.page_mut(page_index).set_value(value_offset, element);
+        self.page_mut(page_index)?.set_value(value_offset, element);
+
+        self.header.len = self.header.len.max(index + 1);
+        Ok(())
     }
 
     // mut because access_time of value mb changed
-    pub fn read(&mut self, index: usize) -> Option<u8> {
-        if index > self.max_index {
-            return None;
+    pub fn read(&mut self, index: usize) -> Option<T> {
+        self.try_read(index)
+            .expect("Failed to read from virtual memory")
+    }
+
+    pub fn try_read(&mut self, index: usize) -> Result<Option<T>> {
+        if index >= self.header.len {
+            return Ok(None);
         }
 
         let page_index = index / self.data_size();
         let value_offset = index % self.data_size();
-        self.page_mut(page_index).get_value(value_offset)
+        Ok(self.page_mut(page_index)?.get_value(value_offset))
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        self.try_remove(index)
+            .expect("Failed to remove from virtual memory")
     }
 
-    pub fn remove(&mut self, index: usize) -> Option<u8> {
+    pub fn try_remove(&mut self, index: usize) -> Result<Option<T>> {
+        if index >= self.header.len {
+            return Ok(None);
+        }
+
         let page_index = index / self.data_size();
         let value_offset = index % self.data_size();
-        let page = self.page_mut(page_index);
-        let value = page.get_value(value_offset);
+        let page = self.page_mut(page_index)?;
+        let value = page.get_value::<T>(value_offset);
         page.remove_value(value_offset);
-        value
+        Ok(value)
     }
 
-    fn page_mut(&mut self, index: usize) -> &mut Page {
-        let page = self.buffer.iter().find(|e| e.index == index);
-        if page.is_none() {
-            self.load_page(index);
+    // write every modified page and the header to the swap source,
+    // pages stay in the buffer
+    pub fn flush(&mut self) -> Result<()> {
+        for frame in 0..self.buffer.len() {
+            self.write_back(frame)?;
         }
+        self.write_header()?;
+        self.swap_source.flush()?;
+        Ok(())
+    }
 
-        self.buffer
-            .iter_mut()
-            .find(|e| e.index == index)
-            .expect("Failed to find page in buffer")
+    // flush and make the swap source durable, survives a power loss
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.swap_source.sync()?;
+        Ok(())
     }
 
-    fn data_size(&self) -> usize {
-        // The data section size is 8/9 of the byte page size
-        // 1/9 is bitmap
-        self.page_size * BITS_IN_BYTE / 9
+    // write the page holding `index` to the swap source if it is modified
+    pub fn flush_page(&mut self, index: usize) -> Result<()> {
+        if let Some(frame) = self.find_frame(index / self.data_size()) {
+            self.write_back(frame)?;
+            self.write_header()?;
+            self.swap_source.flush()?;
+        }
+        Ok(())
     }
 
-    fn page_offset(&self, page_index: usize) -> u64 {
-        (page_index * self.page_size + Self::SIGNATURE.len()) as u64
+    // write `values` starting at `offset`, one page access per page
+    pub fn write_slice(&mut self, offset: usize, values: &[T]) {
+        self.try_write_slice(offset, values)
+            .expect("Failed to write to virtual memory")
     }
 
-    fn is_buffer_full(&self) -> bool {
-        // buffer capacity defined at init,
-        // stays constant during object's lifetime
-        self.buffer.len() == self.buffer.capacity()
+    pub fn try_write_slice(&mut self, offset: usize, values: &[T]) -> Result<()> {
+        let end = Self::slice_end(offset, values.len())?;
+
+        for (page_index, value_offset, range) in self.page_chunks(offset..end) {
+            let chunk = &values[range.start - offset..range.end - offset];
+            self.page_mut(page_index)?.set_values(value_offset, chunk);
+            self.header.len = self.header.len.max(range.end);
+        }
+        Ok(())
+    }
+
+    // fill `buf` with values starting at `offset`, returns ranges of `buf`
+    // with unset values, those bytes are zeroed
+    pub fn read_into(&mut self, offset: usize, buf: &mut [T]) -> Vec<Range<usize>> {
+        self.try_read_into(offset, buf)
+            .expect("Failed to read from virtual memory")
     }
 
-    fn drop_oldest_page(&mut self) {
-        self.buffer
-            .sort_by_key(|e| std::cmp::Reverse(e.last_access));
-        if let Some(last_page) = self.buffer.last() {
-            self.unload_page(last_page.index);
+    pub fn try_read_into(&mut self, offset: usize, buf: &mut [T]) -> Result<Vec<Range<usize>>> {
+        let end = Self::slice_end(offset, buf.len())?;
+        let mut unset: Vec<Range<usize>> = Vec::new();
+        let mut push_unset = |index: usize| match unset.last_mut() {
+            Some(last) if last.end == index => last.end += 1,
+            _ => unset.push(index..index + 1),
+        };
+
+        for (page_index, value_offset, range) in self.page_chunks(offset..end) {
+            let chunk = &mut buf[range.start - offset..range.end - offset];
+            if range.start >= self.header.len {
+                // nothing was ever written there, don't load the page
+                chunk.fill(T::zeroed());
+                (range.start - offset..range.end - offset).for_each(&mut push_unset);
+                continue;
+            }
+
+            let page = self.page_mut(page_index)?;
+            page.get_values(value_offset, chunk, |i| {
+                push_unset(range.start - offset + i)
+            });
         }
+        Ok(unset)
     }
 
-    // load page from file to vec buffer
-    fn load_page(&mut self, page_index: usize) {
-        if self.is_buffer_full() {
-            self.drop_oldest_page();
+    // values in `range`, `None` for unset ones
+    pub fn read_vec(&mut self, range: Range<usize>) -> Vec<Option<T>> {
+        self.try_read_vec(range)
+            .expect("Failed to read from virtual memory")
+    }
+
+    pub fn try_read_vec(&mut self, range: Range<usize>) -> Result<Vec<Option<T>>> {
+        let mut buf = vec![T::zeroed(); range.len()];
+        let unset = self.try_read_into(range.start, &mut buf)?;
+
+        let mut values: Vec<Option<T>> = buf.into_iter().map(Some).collect();
+        for index in unset.into_iter().flatten() {
+            values[index] = None;
         }
+        Ok(values)
+    }
+
+    fn slice_end(offset: usize, len: usize) -> Result<usize> {
+        offset
+            .checked_add(len)
+            .ok_or(Error::IndexOutOfRange { index: offset })
+    }
 
-        // set cursor to the start of the page in the file
-        let offset = SeekFrom::Start(self.page_offset(page_index));
-        self.swap_source.seek(offset).unwrap();
+    // split `range` of indices by pages:
+    // page index, offset of the range start in that page, part of the range
+    fn page_chunks(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, usize, Range<usize>)> {
+        let data_size = self.data_size();
+        let mut start = range.start;
+        std::iter::from_fn(move || {
+            if start >= range.end {
+                return None;
+            }
+            let value_offset = start % data_size;
+            let end = range.end.min(start + (data_size - value_offset));
+            let chunk = (start / data_size, value_offset, start..end);
+            start = end;
+            Some(chunk)
+        })
+    }
+
+    fn page_mut(&mut self, index: usize) -> Result<&mut Page> {
+        let frame = match self.find_frame(index) {
+            Some(frame) => {
+                let now = self.tick();
+                self.buffer[frame]
+                    .as_mut()
+                    .expect("Failed to find page in buffer")
+                    .touch(now);
+                self.policy.access(frame, now);
+                frame
+            }
+            // a loaded page is already stamped with the time of this access
+            None => self.load_page(index)?,
+        };
+
+        Ok(self.buffer[frame]
+            .as_mut()
+            .expect("Failed to find page in buffer"))
+    }
+
+    // monotonic, so pages accessed later always have a greater time
+    // and eviction order doesn't depend on the wall clock
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn find_frame(&self, page_index: usize) -> Option<usize> {
+        self.page_table.get(&page_index).copied()
+    }
 
-        let mut bytes = vec![0u8; self.page_size];
-        self.swap_source
-            .read(&mut bytes)
-            .expect("Failed to read page");
+    // values in one page
+    pub(crate) fn data_size(&self) -> usize {
+        self.header.layout.data_size
+    }
+
+    fn page_offset(&self, page_index: usize) -> Result<u64> {
+        page_index
+            .checked_mul(self.header.layout.page_size)
+            .and_then(|offset| (offset as u64).checked_add(self.header.data_offset))
+            .ok_or(Error::IndexOutOfRange {
+                index: page_index.saturating_mul(self.data_size()),
+            })
+    }
+
+    // page of the swap source holding `page_index`, the header takes the
+    // first pages and `data_offset` is always a multiple of the page size
+    fn store_index(&self, page_index: usize) -> Result<u64> {
+        Ok(self.page_offset(page_index)? / self.header.layout.page_size as u64)
+    }
+
+    // header is always at the start of the swap source, page 0 of any size,
+    // the slot table of compressed pages goes out with it
+    fn write_header(&mut self) -> Result<()> {
+        if let Some(slots) = &mut self.slots {
+            slots.flush(&mut self.swap_source, &mut self.stats)?;
+        }
+        self.stats.retries += write_all_page(&mut self.swap_source, 0, &self.header.encode())?;
+        Ok(())
+    }
+
+    fn is_buffer_full(&self) -> bool {
+        self.free_frames.is_empty()
+    }
+
+    // unload the page chosen by the replacement policy, returns freed frame
+    fn evict_page(&mut self) -> Result<usize> {
+        let frame = self
+            .policy
+            .victim()
+            .expect("Replacement policy found no page to evict");
+        let page_index = self.buffer[frame]
+            .as_ref()
+            .expect("Replacement policy chose a free frame")
+            .index;
+        self.unload_page(page_index)?;
+        Ok(frame)
+    }
+
+    // load page from file to a free frame of the buffer, returns the frame
+    fn load_page(&mut self, page_index: usize) -> Result<usize> {
+        // validate the address before making room for the page
+        let store_index = self.store_index(page_index)?;
+
+        if self.is_buffer_full() {
+            self.evict_page()?;
+        }
 
-        let page = Page::new(page_index, self.page_size, bytes);
-        self.buffer.push(page);
+        let page = self.fetch_page(page_index, store_index)?;
+        let frame = self.free_frames.pop().expect("Failed to free a frame");
+        let now = self.tick();
+        self.buffer[frame] = Some(page);
+        self.buffer[frame].as_mut().unwrap().touch(now);
+        self.page_table.insert(page_index, frame);
+        self.policy.insert(frame, now);
+        Ok(frame)
     }
 
-    fn unload_page(&mut self, page_index: usize) {
+    // page `page_index` as the swap source holds it, empty if it was
+    // never written, or corrupt with `quarantine`
+    fn fetch_page(&mut self, page_index: usize, store_index: u64) -> Result<Page> {
+        let layout = self.header.layout;
+        let mut write_count = 0;
         let page = self
-            .buffer
-            .iter()
-            .find(|e| e.index == page_index)
-            .expect("Failed to find page in buffer");
+            .read_page_bytes(page_index, store_index, &mut write_count)
+            .and_then(|bytes| match bytes {
+                Some(bytes) => Page::new(page_index, &layout, bytes),
+                None => Ok(Page::empty(page_index, &layout)),
+            });
+        let mut page = match page {
+            Ok(page) => page,
+            Err(Error::CorruptPage { index }) => {
+                self.stats.corrupt_pages += 1;
+                if !self.quarantine {
+                    return Err(Error::CorruptPage { index });
+                }
+                if !self.quarantined.contains(&index) {
+                    self.quarantined.push(index);
+                }
+                Page::empty(page_index, &layout)
+            }
+            Err(e) => return Err(e),
+        };
+        page.write_count = write_count;
+        Ok(page)
+    }
 
-        if page.is_modified {
-            let offset = SeekFrom::Start(self.page_offset(page_index));
-            self.swap_source.seek(offset).unwrap();
+    // write the page in `frame` to the swap source if it was modified
+    fn write_back(&mut self, frame: usize) -> Result<()> {
+        if !self.buffer[frame]
+            .as_ref()
+            .is_some_and(|page| page.is_modified)
+        {
+            return Ok(());
+        }
+
+        // out of the buffer while the holes before it are written
+        let mut page = self.buffer[frame].take().unwrap();
+        let result = self
+            .fill_holes(page.index)
+            .and_then(|()| self.store_page(&mut page));
+        self.buffer[frame] = Some(page);
+        result
+    }
 
-            self.swap_source
-                .write(page.bitmap.as_ref())
-                .expect("Failed to write bitmap to swap file");
-            self.swap_source
-                .write(page.values.as_ref())
-                .expect("Failed to write bitmap to swap file");
+    // Pages between the last one written back and `page_index` are zeros
+    // in the swap source, they are written first, see `PageLayout::holes`.
+    // Compressed pages without a slot are empty already.
+    fn fill_holes(&mut self, page_index: usize) -> Result<()> {
+        if self.slots.is_some() {
+            return Ok(());
         }
 
-        self.buffer.retain(|e| e.index != page_index);
+        for hole in self.header.layout.holes(self.header.page_count, page_index) {
+            match self.find_frame(hole) {
+                Some(frame) => {
+                    self.buffer[frame]
+                        .as_mut()
+                        .expect("Failed to find page in buffer")
+                        .is_modified = true;
+                    self.write_back(frame)?;
+                }
+                None => {
+                    let store_index = self.store_index(hole)?;
+                    let mut page = self.fetch_page(hole, store_index)?;
+                    self.store_page(&mut page)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // encode `page`, compressed and sealed as the swap source takes it,
+    // and write it there
+    fn store_page(&mut self, page: &mut Page) -> Result<()> {
+        let page_index = page.index;
+        let store_index = self.store_index(page_index)?;
+        let mut bytes = page.encode(&self.header.layout);
+        let page_bytes = bytes.len();
+        let mut is_compressed = false;
+        if self.slots.is_some() {
+            let compressed = self.header.compression().compress(&bytes);
+            // pages that don't get smaller are stored as they are
+            if compressed.len() < bytes.len() {
+                bytes = compressed;
+                is_compressed = true;
+            }
+        }
+        if let Some(cipher) = &self.cipher {
+            // counted before sealing and never taken back, a failed or
+            // torn write may still leave the sealed bytes in the swap source
+            page.write_count = page.write_count.checked_add(1).ok_or(Error::InvalidConfig(
+                "Page was sealed too many times, its nonces are used up",
+            ))?;
+            cipher.seal(page_index, page.write_count, &mut bytes)?;
+        }
+
+        let stored_bytes = match &mut self.slots {
+            Some(slots) => slots.write(
+                &mut self.swap_source,
+                page_index,
+                &bytes,
+                is_compressed,
+                &mut self.stats,
+            )?,
+            None => {
+                self.stats.retries += write_all_page(&mut self.swap_source, store_index, &bytes)?;
+                bytes.len() as u64
+            }
+        };
+        self.stats.page_writes += 1;
+        self.stats.page_bytes += page_bytes as u64;
+        self.stats.stored_bytes += stored_bytes;
+        self.header.page_count = self.header.page_count.max(page_index + 1);
+
+        // written only once the whole page is in the swap source
+        page.is_modified = false;
+        Ok(())
+    }
+
+    // plain bytes of page `page_index` as `Page::new` takes them, `None`
+    // for a page that was never written, `write_count` is read from
+    // a sealed page even if it turns out corrupt, so the page is never
+    // sealed again with the same nonce
+    fn read_page_bytes(
+        &mut self,
+        page_index: usize,
+        store_index: u64,
+        write_count: &mut u32,
+    ) -> Result<Option<Vec<u8>>> {
+        let layout = self.header.layout;
+        let (mut bytes, is_compressed) = match &mut self.slots {
+            // never written back, whatever the swap source holds there
+            // isn't ours, but a page sealed before the header was saved
+            // still used up its nonces
+            _ if page_index >= self.header.page_count => {
+                if self.cipher.is_some() && self.slots.is_none() {
+                    self.read_write_count(page_index, store_index, write_count)?;
+                }
+                self.stats.zero_pages += 1;
+                return Ok(None);
+            }
+            Some(slots) => match slots.read(&mut self.swap_source, page_index, &mut self.stats)? {
+                Some(stored) => stored,
+                None => {
+                    self.stats.zero_pages += 1;
+                    return Ok(None);
+                }
+            },
+            None => {
+                let mut bytes = vec![0u8; layout.page_size];
+                self.stats.retries +=
+                    read_exact_page(&mut self.swap_source, store_index, &mut bytes)?;
+                (bytes, false)
+            }
+        };
+        self.stats.page_reads += 1;
+
+        if let Some(cipher) = &self.cipher {
+            *write_count = PageCipher::write_count(&bytes);
+            cipher.open(page_index, &mut bytes)?;
+        }
+        if is_compressed {
+            let mut plain = vec![0u8; layout.plain_size()];
+            self.header
+                .compression()
+                .decompress(page_index, &bytes, &mut plain)?;
+            bytes = plain;
+        }
+        if bytes.len() != layout.plain_size() {
+            return Err(Error::CorruptPage { index: page_index });
+        }
+        Ok(Some(bytes))
+    }
+
+    // write count of a sealed page past `page_count` that the swap
+    // source holds anyway, checked like any other sealed page
+    fn read_write_count(
+        &mut self,
+        page_index: usize,
+        store_index: u64,
+        write_count: &mut u32,
+    ) -> Result<()> {
+        let page_size = self.header.layout.page_size as u64;
+        if store_index.saturating_mul(page_size) >= self.swap_source.len()? {
+            return Ok(());
+        }
+
+        let mut bytes = vec![0u8; self.header.layout.page_size];
+        self.stats.retries += read_exact_page(&mut self.swap_source, store_index, &mut bytes)?;
+        self.stats.page_reads += 1;
+        if let Some(cipher) = &self.cipher {
+            *write_count = PageCipher::write_count(&bytes);
+            cipher.open(page_index, &mut bytes)?;
+        }
+        Ok(())
+    }
+
+    fn unload_page(&mut self, page_index: usize) -> Result<()> {
+        let frame = self
+            .find_frame(page_index)
+            .expect("Failed to find page in buffer");
+        self.write_back(frame)?;
+
+        self.buffer[frame] = None;
+        self.page_table.remove(&page_index);
+        self.free_frames.push(frame);
+        self.policy.remove(frame);
+        Ok(())
+    }
+}
+
+// a page being loaded and one being evicted need a frame each
+pub(crate) fn check_buffer_size(buffer_size: usize) -> Result<()> {
+    if buffer_size <= 2 {
+        return Err(Error::InvalidConfig(
+            "Virtual memory should have buffer size > 2",
+        ));
     }
+    Ok(())
 }
 
-impl<RWS> Drop for VirtualMemory<RWS>
+impl<S, T> Drop for VirtualMemory<S, T>
 where
-    RWS: Read + Write + Seek,
+    S: PageStore,
+    T: Pod,
 {
     fn drop(&mut self) {
-        while let Some(last_page) = self.buffer.last() {
-            self.unload_page(last_page.index);
+        // errors can't be reported from drop, use `flush` to see them
+        for frame in 0..self.buffer.len() {
+            let _ = self.write_back(frame);
         }
+        let _ = self.write_header();
+        let _ = self.swap_source.flush();
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::VirtualMemory;
+    use crate::checksum::Checksum;
+    use crate::compression::Compression;
+    use crate::encryption::Encryption;
+    use crate::options::Options;
+    use crate::page_store::PageStore;
+    use crate::policy::Fifo;
+    use crate::Error;
+    use std::io::{self, Cursor};
     use tempfile::tempfile;
 
     #[test]
@@ -192,9 +800,10 @@ mod test {
         let swap_file = tempfile().unwrap();
         let vm = VirtualMemory::new(swap_file, 16, 3);
         // page size (16) = bitmap size (2) + values size (14)
-        assert_eq!(vm.page_offset(0), 2);
-        assert_eq!(vm.page_offset(1), 18);
-        assert_eq!(vm.page_offset(2), 34);
+        // header (64) takes the first 4 pages
+        assert_eq!(vm.page_offset(0).unwrap(), 64);
+        assert_eq!(vm.page_offset(1).unwrap(), 80);
+        assert_eq!(vm.page_offset(2).unwrap(), 96);
     }
 
     #[test]
@@ -208,25 +817,189 @@ mod test {
         assert!(vm.is_buffer_full());
     }
 
+    fn resident_pages<S>(vm: &VirtualMemory<S>) -> Vec<usize>
+    where
+        S: PageStore,
+    {
+        vm.buffer.iter().flatten().map(|page| page.index).collect()
+    }
+
     #[test]
-    fn drop_oldest_page() {
+    fn evict_page() {
         let swap_file = tempfile().unwrap();
         let mut vm = VirtualMemory::new(swap_file, 16, 3);
         vm.write(0, 1);
         vm.write(16, 2);
         vm.write(32, 3);
-        vm.drop_oldest_page();
-        assert_eq!(vm.buffer.len(), 2);
-        assert_eq!(vm.buffer[0].index, 2);
-        assert_eq!(vm.buffer[1].index, 1);
+        vm.read(0);
+        // page 1 is the least recently used one
+        assert_eq!(vm.evict_page().unwrap(), 1);
+        assert_eq!(resident_pages(&vm), vec![0, 2]);
+    }
+
+    #[test]
+    fn logical_clock() {
+        let swap_file = tempfile().unwrap();
+        let mut vm = VirtualMemory::new(swap_file, 16, 3);
+        // a fault on page 0, then an access of page 0
+        vm.write(0, 1);
+        vm.read(0);
+        // a fault on page 1
+        vm.write(16, 2);
+
+        let last_access =
+            |vm: &VirtualMemory<_>, frame: usize| vm.buffer[frame].as_ref().unwrap().last_access;
+        // one tick per access, a fault included
+        assert_eq!(vm.clock, 3);
+        assert_eq!(last_access(&vm, 0), 2);
+        assert_eq!(last_access(&vm, 1), 3);
+    }
+
+    #[test]
+    fn set_policy() {
+        let swap_file = tempfile().unwrap();
+        let mut vm = VirtualMemory::new(swap_file, 16, 3);
+        vm.write(16, 2);
+        vm.write(0, 1);
+        vm.set_policy(Fifo::new());
+        vm.write(32, 3);
+        vm.read(16);
+        // page 1 was accessed first, so the new policy sees it first,
+        // reading it again doesn't matter
+        assert_eq!(vm.evict_page().unwrap(), 0);
+        assert_eq!(resident_pages(&vm), vec![0, 2]);
     }
 
     #[test]
     fn load_page() {
         let swap_file = tempfile().unwrap();
         let mut vm = VirtualMemory::new(swap_file, 16, 3);
-        vm.load_page(0);
-        assert_eq!(vm.buffer.len(), 1);
+        vm.load_page(0).unwrap();
+        assert_eq!(resident_pages(&vm), vec![0]);
+    }
+
+    #[test]
+    fn slices_cross_pages() {
+        let swap_file = tempfile().unwrap();
+        // 14 values per page
+        let mut vm = VirtualMemory::new(swap_file, 16, 3);
+        let values: Vec<u8> = (0..40).collect();
+        vm.write_slice(10, &values);
+        assert_eq!(vm.len(), 50);
+
+        let mut buf = [0xff; 44];
+        let unset = vm.read_into(8, &mut buf);
+        assert_eq!(unset, vec![0..2, 42..44]);
+        assert_eq!(&buf[2..42], values.as_slice());
+        assert_eq!(&buf[..2], [0, 0]);
+
+        let read = vm.read_vec(48..52);
+        assert_eq!(read, vec![Some(38), Some(39), None, None]);
+    }
+
+    #[test]
+    fn read_past_len_loads_nothing() {
+        let swap_file = tempfile().unwrap();
+        let mut vm = VirtualMemory::new(swap_file, 16, 3);
+        vm.write(0, 1);
+        let unset = vm.try_read_into(100, &mut [0; 30]).unwrap();
+        assert_eq!(unset, vec![0..30]);
+        assert_eq!(resident_pages(&vm), vec![0]);
+    }
+
+    fn is_modified<S>(vm: &VirtualMemory<S>, page_index: usize) -> bool
+    where
+        S: PageStore,
+    {
+        let frame = vm.find_frame(page_index).unwrap();
+        vm.buffer[frame].as_ref().unwrap().is_modified
+    }
+
+    #[test]
+    fn flush_keeps_pages() {
+        let mut swap_file = tempfile().unwrap();
+        let mut vm = VirtualMemory::new(swap_file.try_clone().unwrap(), 16, 3);
+        vm.write(0, 1);
+        vm.write(20, 2);
+        vm.flush().unwrap();
+
+        assert_eq!(resident_pages(&vm), vec![0, 1]);
+        assert!(!is_modified(&vm, 0));
+        assert!(!is_modified(&vm, 1));
+
+        // another handle sees the flushed contents while `vm` is alive
+        let mut reopened = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        assert_eq!(reopened.read(0), Some(1));
+        assert_eq!(reopened.read(20), Some(2));
+    }
+
+    #[test]
+    fn flush_page() {
+        let swap_file = tempfile().unwrap();
+        let mut vm = VirtualMemory::new(swap_file, 16, 3);
+        vm.write(0, 1);
+        vm.write(20, 2);
+        vm.flush_page(15).unwrap();
+
+        assert!(is_modified(&vm, 0));
+        assert!(!is_modified(&vm, 1));
+        // page isn't in the buffer, nothing to do
+        vm.flush_page(100).unwrap();
+        vm.sync().unwrap();
+        assert!(!is_modified(&vm, 0));
+    }
+
+    #[test]
+    fn try_new_invalid_config() {
+        assert!(VirtualMemory::try_new(tempfile().unwrap(), 1, 3).is_err());
+        assert!(VirtualMemory::try_new(tempfile().unwrap(), 16, 2).is_err());
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let swap_file = tempfile().unwrap();
+        let mut vm = VirtualMemory::new(swap_file, 16, 3);
+        assert!(matches!(
+            vm.try_write(usize::MAX, 1),
+            Err(Error::IndexOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn open_bad_header() {
+        let empty = Cursor::new(Vec::new());
+        assert!(matches!(
+            VirtualMemory::open(empty, 3),
+            Err(Error::MissingHeader)
+        ));
+
+        let mut foreign = vec![0u8; 64];
+        foreign[..2].copy_from_slice(b"MZ");
+        assert!(matches!(
+            VirtualMemory::open(Cursor::new(foreign), 3),
+            Err(Error::BadSignature)
+        ));
+
+        let mut zero_header = vec![0u8; 64];
+        zero_header[..2].copy_from_slice(b"VM");
+        assert!(matches!(
+            VirtualMemory::open(Cursor::new(zero_header), 3),
+            Err(Error::CorruptHeader(_))
+        ));
+    }
+
+    #[test]
+    fn header_high_water_marks() {
+        let mut swap_file = Cursor::new(Vec::new());
+        {
+            let mut vm = VirtualMemory::new(&mut swap_file, 16, 3);
+            vm.write(30, 1);
+        }
+
+        let vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        assert_eq!(vm.header.layout.page_size, 16);
+        assert_eq!(vm.len(), 31);
+        assert_eq!(vm.header.page_count, 3);
     }
 
     #[test]
@@ -234,7 +1007,324 @@ mod test {
         let swap_file = tempfile().unwrap();
         let mut vm = VirtualMemory::new(swap_file, 8, 3);
         vm.write(0, 1);
-        vm.unload_page(0);
-        assert_eq!(vm.buffer.len(), 0);
+        vm.unload_page(0).unwrap();
+        assert!(resident_pages(&vm).is_empty());
+    }
+
+    #[test]
+    fn never_written_pages_are_not_read() {
+        let mut swap_file = Cursor::new(Vec::new());
+        {
+            let mut vm = VirtualMemory::new(&mut swap_file, 16, 3);
+            vm.write(0, 1);
+            vm.write(20, 2);
+            assert_eq!(vm.stats().zero_pages, 2);
+            assert_eq!(vm.stats().page_reads, 0);
+        }
+        assert_eq!(swap_file.get_ref().len(), 64 + 2 * 16);
+
+        let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        assert_eq!(vm.read(20), Some(2));
+        vm.write(100, 3);
+        vm.flush().unwrap();
+        let stats = vm.stats();
+        assert_eq!((stats.page_reads, stats.zero_pages), (1, 1));
+        assert_eq!(stats.page_writes, 1);
+    }
+
+    #[test]
+    fn truncated_page() {
+        let mut swap_file = Cursor::new(Vec::new());
+        VirtualMemory::new(&mut swap_file, 16, 3).write(20, 2);
+        swap_file.get_mut().truncate(64 + 16 + 8);
+
+        // the header says page 1 was written, but it ends halfway
+        let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        assert!(
+            matches!(vm.try_read(20), Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof)
+        );
+        assert_eq!(vm.read(0), None);
+    }
+
+    fn checksummed(checksum: Checksum) -> Cursor<Vec<u8>> {
+        let mut swap_file = Cursor::new(Vec::new());
+        let options = Options {
+            checksum,
+            ..Options::default()
+        };
+        let mut vm =
+            VirtualMemory::<_, u8>::try_new_with_options(&mut swap_file, 32, 3, options).unwrap();
+        for i in 0..100 {
+            vm.write(i, i as u8);
+        }
+        drop(vm);
+        swap_file
+    }
+
+    #[test]
+    fn corrupt_page_is_detected() {
+        for checksum in [Checksum::Crc32c, Checksum::XxHash64] {
+            let mut swap_file = checksummed(checksum);
+            // a value of page 1, 64 bytes of header come first
+            swap_file.get_mut()[64 + 32 + 10] ^= 0x04;
+
+            let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+            assert_eq!(vm.header.layout.checksum, checksum);
+            assert_eq!(vm.read(0), Some(0));
+            let index = vm.data_size();
+            assert!(matches!(
+                vm.try_read(index),
+                Err(Error::CorruptPage { index: 1 })
+            ));
+            assert_eq!(vm.stats().corrupt_pages, 1);
+        }
+    }
+
+    #[test]
+    fn zeroed_page_is_corrupt() {
+        let mut swap_file = checksummed(Checksum::Crc32c);
+        swap_file.get_mut()[64 + 32..64 + 64].fill(0);
+
+        let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        let index = vm.data_size();
+        assert!(matches!(
+            vm.try_read(index),
+            Err(Error::CorruptPage { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn holes_are_written_with_checksums() {
+        let mut swap_file = Cursor::new(Vec::new());
+        let options = Options {
+            checksum: Checksum::Crc32c,
+            ..Options::default()
+        };
+        {
+            let mut vm =
+                VirtualMemory::<_, u8>::try_new_with_options(&mut swap_file, 32, 3, options)
+                    .unwrap();
+            // pages 0 to 3 are never written
+            let index = 4 * vm.data_size();
+            vm.write(index, 1);
+        }
+        assert_eq!(swap_file.get_ref().len(), 64 + 5 * 32);
+
+        let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        for i in 0..vm.len() - 1 {
+            assert_eq!(vm.try_read(i).unwrap(), None);
+        }
+        assert_eq!(vm.stats().corrupt_pages, 0);
+    }
+
+    #[test]
+    fn quarantine_corrupt_page() {
+        let mut swap_file = checksummed(Checksum::Crc32c);
+        swap_file.get_mut()[64 + 32 + 10] ^= 0x04;
+
+        let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        vm.set_quarantine(true);
+        let data_size = vm.data_size();
+        assert_eq!(vm.read(data_size), None);
+        assert_eq!(vm.read(2 * data_size), Some(2 * data_size as u8));
+        assert_eq!(vm.quarantined(), [1]);
+
+        // written again, the page is whole
+        vm.write(data_size, 7);
+        drop(vm);
+        let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+        assert_eq!(vm.read(data_size), Some(7));
+        assert!(vm.quarantined().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(feature = "lz4"))]
+    fn compression_needs_its_feature() {
+        let options = Options {
+            compression: Compression::Lz4,
+            ..Options::default()
+        };
+        assert!(matches!(
+            VirtualMemory::<_, u8>::try_new_with_options(Cursor::new(Vec::new()), 32, 3, options),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn compressed_pages_are_smaller() {
+        let options = Options {
+            compression: Compression::Lz4,
+            ..Options::default()
+        };
+        let mut vm =
+            VirtualMemory::<_, u8>::try_new_with_options(Cursor::new(Vec::new()), 256, 3, options)
+                .unwrap();
+        assert_eq!(vm.stats().compression_ratio(), 1.0);
+        for i in 0..2000 {
+            vm.write(i, 7);
+        }
+        vm.flush().unwrap();
+
+        let stats = vm.stats();
+        assert_eq!(stats.page_bytes, stats.page_writes * 256);
+        // every page takes a whole block of one sector
+        assert_eq!(stats.stored_bytes, stats.page_writes * 64);
+        assert!(stats.compression_ratio() > 2.0);
+        // the header, then blocks of compressed pages
+        assert!(vm.swap_source.get_ref().len() < 2000);
+    }
+
+    #[test]
+    #[cfg(not(feature = "aes-gcm"))]
+    fn encryption_needs_its_feature() {
+        let options = Options {
+            encryption: Encryption::Aes256Gcm,
+            ..Options::default()
+        };
+        assert!(matches!(
+            VirtualMemory::<_, u8>::try_new_with_key(
+                Cursor::new(Vec::new()),
+                64,
+                3,
+                options,
+                &[0u8; 32]
+            ),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "aes-gcm")]
+    fn encrypted_pages() {
+        let key = [42u8; 32];
+        let options = Options {
+            encryption: Encryption::Aes256Gcm,
+            ..Options::default()
+        };
+        let mut swap_file = Cursor::new(Vec::new());
+        assert!(matches!(
+            VirtualMemory::<_, u8>::try_new_with_options(&mut swap_file, 64, 3, options),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let mut vm =
+            VirtualMemory::<_, u8>::try_new_with_key(&mut swap_file, 64, 3, options, &key).unwrap();
+        for i in 0..200 {
+            vm.write(i, b'x');
+        }
+        vm.flush().unwrap();
+        let first = vm.swap_source.get_ref()[64..128].to_vec();
+        // the same values again get another nonce
+        vm.write(0, b'x');
+        vm.flush().unwrap();
+        assert_eq!(
+            vm.buffer
+                .iter()
+                .flatten()
+                .find(|page| page.index == 0)
+                .unwrap()
+                .write_count,
+            2
+        );
+        assert_ne!(vm.swap_source.get_ref()[64..128], first[..]);
+        drop(vm);
+        assert!(!swap_file
+            .get_ref()
+            .windows(8)
+            .any(|bytes| bytes == b"xxxxxxxx"));
+
+        assert!(matches!(
+            VirtualMemory::open(&mut swap_file, 3),
+            Err(Error::InvalidConfig(_))
+        ));
+        let mut vm = VirtualMemory::<_, u8>::open_with_key(&mut swap_file, 3, &[7u8; 32]).unwrap();
+        assert!(matches!(
+            vm.try_read(0),
+            Err(Error::CorruptPage { index: 0 })
+        ));
+        drop(vm);
+
+        swap_file.get_mut()[64 + 64 + 5] ^= 1;
+        let mut vm = VirtualMemory::<_, u8>::open_with_key(&mut swap_file, 3, &key).unwrap();
+        assert_eq!(vm.read(0), Some(b'x'));
+        assert_eq!(vm.read(199), Some(b'x'));
+        let index = vm.data_size();
+        assert!(matches!(
+            vm.try_read(index),
+            Err(Error::CorruptPage { index: 1 })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "aes-gcm")]
+    fn failed_write_uses_up_its_nonce() {
+        use crate::encryption::PageCipher;
+        use crate::faulty_store::{Fault, FaultyStore};
+
+        let options = Options {
+            encryption: Encryption::Aes256Gcm,
+            ..Options::default()
+        };
+        let mut store = FaultyStore::new(Cursor::new(Vec::new()));
+        let mut vm =
+            VirtualMemory::<_, u8>::try_new_with_key(&mut store, 64, 3, options, &[42u8; 32])
+                .unwrap();
+        vm.write(0, 1);
+        // the sealed page lands, but the write reports an error
+        let writes = vm.swap_source.writes();
+        vm.swap_source.fail_write(writes, Fault::Torn(64));
+        assert!(vm.flush().is_err());
+        let first = vm.swap_source.get_ref().get_ref()[64..128].to_vec();
+        vm.flush().unwrap();
+
+        let second = &vm.swap_source.get_ref().get_ref()[64..128];
+        assert_eq!(PageCipher::write_count(&first), 1);
+        assert_eq!(PageCipher::write_count(second), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "aes-gcm")]
+    fn sealed_pages_past_page_count() {
+        use crate::encryption::PageCipher;
+
+        let key = [42u8; 32];
+        let options = Options {
+            encryption: Encryption::Aes256Gcm,
+            ..Options::default()
+        };
+        let mut swap_file = Cursor::new(Vec::new());
+        let mut vm =
+            VirtualMemory::<_, u8>::try_new_with_key(&mut swap_file, 64, 3, options, &key).unwrap();
+        vm.write(0, 1);
+        vm.flush().unwrap();
+        let header = vm.swap_source.get_ref()[..64].to_vec();
+        let index = vm.data_size();
+        vm.write(index, 2);
+        drop(vm);
+
+        // page 1 made it, the header saying so didn't
+        swap_file.get_mut()[..64].copy_from_slice(&header);
+        let mut vm = VirtualMemory::<_, u8>::open_with_key(&mut swap_file, 3, &key).unwrap();
+        vm.write(index + 1, 3);
+        assert_eq!(vm.read(index), None);
+        drop(vm);
+        assert_eq!(PageCipher::write_count(&swap_file.get_ref()[128..192]), 2);
+
+        // a zeroed page doesn't open, below `page_count` or past it
+        swap_file.get_mut()[..64].copy_from_slice(&header);
+        swap_file.get_mut()[128..192].fill(0);
+        let mut vm = VirtualMemory::<_, u8>::open_with_key(&mut swap_file, 3, &key).unwrap();
+        assert!(matches!(
+            vm.try_write(index, 4),
+            Err(Error::CorruptPage { index: 1 })
+        ));
+        drop(vm);
+        swap_file.get_mut()[64..128].fill(0);
+        let mut vm = VirtualMemory::<_, u8>::open_with_key(&mut swap_file, 3, &key).unwrap();
+        assert!(matches!(
+            vm.try_read(0),
+            Err(Error::CorruptPage { index: 0 })
+        ));
     }
 }
@@ -0,0 +1,292 @@
+use crate::error::{Error, Result};
+use crate::page_store::PageStore;
+use crate::pod::Pod;
+use crate::virtual_memory::VirtualMemory;
+use std::ops::Range;
+
+// values moved or read at once by shifting and iteration
+const CHUNK: usize = 1024;
+
+// Growable vector of values kept in virtual memory, value `i` at index
+// `i`. Its length is kept in the swap source header and saved with it,
+// by `flush` and drop.
+#[derive(Debug)]
+pub struct VVec<S, T = u8>
+where
+    S: PageStore,
+    T: Pod,
+{
+    vm: VirtualMemory<S, T>,
+}
+
+impl<S, T> VVec<S, T>
+where
+    S: PageStore,
+    T: Pod,
+{
+    pub fn new(swap_source: S, page_size: usize, buffer_size: usize) -> Result<Self> {
+        let vm = VirtualMemory::try_new_typed(swap_source, page_size, buffer_size)?;
+        Ok(VVec { vm })
+    }
+
+    // reopen swap source created by `new`, keeping its length
+    pub fn open(swap_source: S, buffer_size: usize) -> Result<Self> {
+        let vm = VirtualMemory::open_typed(swap_source, buffer_size)?;
+        // values past the length were written once, so it never passes them
+        if vm.container_len() > vm.len() {
+            return Err(Error::CorruptHeader("container length"));
+        }
+        Ok(VVec { vm })
+    }
+
+    pub fn len(&self) -> usize {
+        self.vm.container_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&mut self, index: usize) -> Result<Option<T>> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+        self.vm.try_read(index)
+    }
+
+    // replace an existing value
+    pub fn set(&mut self, index: usize, value: T) -> Result<()> {
+        self.check_index(index, self.len())?;
+        self.vm.try_write(index, value)
+    }
+
+    pub fn push(&mut self, value: T) -> Result<()> {
+        let len = self.len();
+        self.vm.try_write(len, value)?;
+        self.vm.set_container_len(len + 1);
+        Ok(())
+    }
+
+    // values past the length stay in the swap source, they are
+    // unreachable and overwritten by the next push
+    pub fn pop(&mut self) -> Result<Option<T>> {
+        let len = match self.len() {
+            0 => return Ok(None),
+            len => len - 1,
+        };
+        let value = self.vm.try_read(len)?;
+        self.vm.set_container_len(len);
+        Ok(value)
+    }
+
+    pub fn extend_from_slice(&mut self, values: &[T]) -> Result<()> {
+        let len = self.len();
+        self.vm.try_write_slice(len, values)?;
+        self.vm.set_container_len(len + values.len());
+        Ok(())
+    }
+
+    // shorten to `len` values, no effect if the vector is shorter
+    pub fn truncate(&mut self, len: usize) {
+        self.vm.set_container_len(self.len().min(len));
+    }
+
+    // insert `value` at `index`, shifting every value after it right
+    pub fn insert(&mut self, index: usize, value: T) -> Result<()> {
+        let len = self.len();
+        self.check_index(index, len + 1)?;
+        self.copy_within(index..len, index + 1)?;
+        self.vm.try_write(index, value)?;
+        self.vm.set_container_len(len + 1);
+        Ok(())
+    }
+
+    // remove the value at `index`, shifting every value after it left,
+    // fails with `Error::UnsetValue` if it was never written
+    pub fn remove(&mut self, index: usize) -> Result<T> {
+        let len = self.len();
+        self.check_index(index, len)?;
+        let value = self
+            .vm
+            .try_read(index)?
+            .ok_or(Error::UnsetValue { index })?;
+        self.copy_within(index + 1..len, index)?;
+        self.vm.set_container_len(len - 1);
+        Ok(value)
+    }
+
+    // values in index order, read a chunk at a time
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<T>> + '_ {
+        let mut chunk: Vec<T> = Vec::new();
+        let mut index = 0;
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed || index >= self.len() {
+                return None;
+            }
+
+            let position = index % CHUNK;
+            if position == 0 {
+                let end = self.len().min(index + CHUNK);
+                chunk.resize(end - index, T::zeroed());
+                if let Err(error) = self.vm.try_read_into(index, &mut chunk) {
+                    failed = true;
+                    return Some(Err(error));
+                }
+            }
+
+            index += 1;
+            Some(Ok(chunk[position]))
+        })
+    }
+
+    // write every modified page and the length to the swap source
+    pub fn flush(&mut self) -> Result<()> {
+        self.vm.flush()
+    }
+
+    fn check_index(&self, index: usize, bound: usize) -> Result<()> {
+        if index >= bound {
+            return Err(Error::IndexOutOfRange { index });
+        }
+        Ok(())
+    }
+
+    // move values in `src` to start at `dest`, ranges may overlap,
+    // unset values stay unset
+    fn copy_within(&mut self, src: Range<usize>, dest: usize) -> Result<()> {
+        let mut chunk = vec![T::zeroed(); CHUNK.min(src.len())];
+        let mut copy = |vm: &mut VirtualMemory<S, T>, start: usize, end: usize| {
+            let buf = &mut chunk[..end - start];
+            let unset = vm.try_read_into(start, buf)?;
+            let to = dest + (start - src.start);
+            vm.try_write_slice(to, buf)?;
+            for index in unset.into_iter().flatten() {
+                vm.try_remove(to + index)?;
+            }
+            Ok::<_, Error>(())
+        };
+
+        if dest > src.start {
+            // moving right, copy from the end so nothing is overwritten
+            let mut end = src.end;
+            while end > src.start {
+                let start = end.saturating_sub(CHUNK).max(src.start);
+                copy(&mut self.vm, start, end)?;
+                end = start;
+            }
+        } else {
+            let mut start = src.start;
+            while start < src.end {
+                let end = src.end.min(start + CHUNK);
+                copy(&mut self.vm, start, end)?;
+                start = end;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VVec;
+    use crate::virtual_memory::VirtualMemory;
+    use crate::Error;
+    use std::io::Cursor;
+
+    fn collect(vec: &mut VVec<Cursor<Vec<u8>>, u32>) -> Vec<u32> {
+        vec.iter().map(|v| v.unwrap()).collect()
+    }
+
+    #[test]
+    fn push_pop() {
+        let mut vec = VVec::<_, u32>::new(Cursor::new(Vec::new()), 64, 3).unwrap();
+        for i in 0..100 {
+            vec.push(i).unwrap();
+        }
+        assert_eq!(vec.len(), 100);
+        assert_eq!(vec.pop().unwrap(), Some(99));
+        assert_eq!(vec.get(99).unwrap(), None);
+        assert_eq!(vec.get(98).unwrap(), Some(98));
+
+        vec.truncate(1);
+        assert_eq!(vec.pop().unwrap(), Some(0));
+        assert_eq!(vec.pop().unwrap(), None);
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn extend_and_iter() {
+        let mut vec = VVec::<_, u32>::new(Cursor::new(Vec::new()), 64, 3).unwrap();
+        let values: Vec<u32> = (0..3000).collect();
+        vec.extend_from_slice(&values[..1500]).unwrap();
+        vec.extend_from_slice(&values[1500..]).unwrap();
+        assert_eq!(collect(&mut vec), values);
+    }
+
+    #[test]
+    fn insert_remove_shift() {
+        let mut vec = VVec::<_, u32>::new(Cursor::new(Vec::new()), 64, 3).unwrap();
+        let mut expected: Vec<u32> = (0..2500).collect();
+        vec.extend_from_slice(&expected).unwrap();
+
+        vec.insert(3, 7000).unwrap();
+        expected.insert(3, 7000);
+        vec.insert(vec.len(), 7001).unwrap();
+        expected.push(7001);
+        assert_eq!(vec.remove(10).unwrap(), expected.remove(10));
+        assert_eq!(vec.remove(0).unwrap(), expected.remove(0));
+        assert_eq!(collect(&mut vec), expected);
+
+        let len = vec.len();
+        assert!(matches!(
+            vec.insert(len + 1, 0),
+            Err(Error::IndexOutOfRange { .. })
+        ));
+        assert!(matches!(
+            vec.remove(len),
+            Err(Error::IndexOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn shifting_keeps_unset_values() {
+        let mut vec = VVec::<_, u32>::new(Cursor::new(Vec::new()), 64, 3).unwrap();
+        vec.extend_from_slice(&[1, 2, 3]).unwrap();
+        // an unset value among set ones
+        vec.vm.try_remove(1).unwrap();
+        vec.insert(0, 9).unwrap();
+        assert_eq!(vec.get(1).unwrap(), Some(1));
+        assert_eq!(vec.get(2).unwrap(), None);
+        assert_eq!(vec.get(3).unwrap(), Some(3));
+        vec.remove(0).unwrap();
+        assert_eq!(vec.get(1).unwrap(), None);
+
+        // an unset value has nothing to return, the vector stays as it is
+        assert!(matches!(vec.remove(1), Err(Error::UnsetValue { index: 1 })));
+        assert_eq!(vec.len(), 3);
+        assert_eq!(vec.get(2).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn reopen() {
+        let mut swap_source = Cursor::new(Vec::new());
+        {
+            let mut vec = VVec::<_, u32>::new(&mut swap_source, 64, 3).unwrap();
+            vec.extend_from_slice(&[1, 2, 3, 4, 5]).unwrap();
+            vec.pop().unwrap();
+        }
+
+        {
+            let mut vec = VVec::<_, u32>::open(&mut swap_source, 3).unwrap();
+            assert_eq!(vec.len(), 4);
+            assert_eq!(vec.get(3).unwrap(), Some(4));
+            assert_eq!(vec.get(4).unwrap(), None);
+        }
+
+        // values sit at their own index, the length in the header
+        let mut vm = VirtualMemory::<_, u32>::open_typed(&mut swap_source, 3).unwrap();
+        assert_eq!(vm.read(0), Some(1));
+        assert_eq!(vm.container_len(), 4);
+    }
+}
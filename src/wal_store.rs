@@ -0,0 +1,506 @@
+use crate::checksum::crc32c;
+use crate::error::Result;
+use crate::page_store::{page_offset, read_exact_page, write_all_page, PageStore};
+use std::collections::BTreeMap;
+use std::io;
+
+// writes are split into frames of the log
+const FRAME_SIZE: usize = 512;
+const FRAME_HEADER: usize = 32;
+const FRAME_DATA: usize = FRAME_SIZE - FRAME_HEADER;
+const SIGNATURE: &[u8; 4] = b"VWAL";
+// two copies of the log header come first, a torn write leaves one
+const FIRST_RECORD_FRAME: u64 = 2;
+// frame kinds
+const WRITE: u8 = 1;
+const MORE: u8 = 2;
+const COMMIT: u8 = 3;
+
+// page index, length and first log frame of a logged write
+type LoggedWrite = (u64, usize, u64);
+
+// Page store that writes to a log first, so a crash never leaves `inner`
+// with half of an update. Every write goes to `log` and is read back from
+// there, `flush` commits the writes so far, and a checkpoint copies the
+// committed writes to `inner` and empties the log, on the first write or
+// flush once the log reaches the checkpoint size with nothing uncommitted.
+// Only the log frame of every write is kept in memory. `open` replays the
+// writes committed before a crash, the ones after the last commit are lost,
+// and so are writes after a failed one: the store fails until opened again.
+// `VirtualMemory` flushes its pages and header together, so the swap
+// source is always as of one of its flushes.
+//
+// log header, frames 0 and 1:
+//  0..4    CRC-32C of bytes 4..512
+//  4..12   generation, bumped by every checkpoint
+// 12..16   signature "VWAL"
+//
+// frames 2.., 512 bytes each:
+//  0..4    CRC-32C of bytes 4..512
+//  4..12   generation, frames of older ones are stale
+// 12       kind: 1 starts a write, 2 carries more of it, 3 commits
+// 16..24   page index of the write
+// 24..28   length of the write
+// 32..512  bytes of the write
+#[derive(Debug)]
+pub struct WalStore<S, L> {
+    inner: S,
+    log: L,
+    generation: u64,
+    // next frame of the log to write
+    next_frame: u64,
+    // first log frame of every write since the last checkpoint,
+    // by byte offset and length, later frames win where writes overlap
+    writes: BTreeMap<(u64, usize), u64>,
+    // longest of those writes, how far before a read one can start
+    max_write: usize,
+    // writes were logged after the last commit
+    is_uncommitted: bool,
+    // a log write failed, committing now could commit half of an update
+    is_failed: bool,
+    // end of the logged writes
+    len: u64,
+    // log size in bytes that makes a commit checkpoint
+    checkpoint_size: u64,
+}
+
+impl<S, L> WalStore<S, L>
+where
+    S: PageStore,
+    L: PageStore,
+{
+    const DEFAULT_CHECKPOINT_SIZE: u64 = 4 << 20;
+
+    // replay the writes committed to `log` onto `inner`,
+    // a log without a header is started anew
+    pub fn open(inner: S, log: L) -> Result<Self> {
+        let mut store = WalStore {
+            inner,
+            log,
+            generation: 0,
+            next_frame: FIRST_RECORD_FRAME,
+            writes: BTreeMap::new(),
+            max_write: 0,
+            is_uncommitted: false,
+            is_failed: false,
+            len: 0,
+            checkpoint_size: Self::DEFAULT_CHECKPOINT_SIZE,
+        };
+
+        let generation = [0, 1]
+            .into_iter()
+            .map(|frame| store.read_header(frame))
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .max();
+        let generation = match generation {
+            Some(generation) => generation,
+            None => {
+                store.start_generation(1)?;
+                return Ok(store);
+            }
+        };
+        store.generation = generation;
+
+        let committed = store.replay()?;
+        for &(index, len, frame) in &committed {
+            let bytes = store.read_logged(frame, len)?;
+            write_all_page(&mut store.inner, index, &bytes)?;
+        }
+        if !committed.is_empty() {
+            store.inner.sync()?;
+        }
+        // stale frames of this generation must never join later ones,
+        // even past a first frame that is torn or was never written
+        store.start_generation(generation + 1)?;
+        Ok(store)
+    }
+
+    // checkpoint once the log reaches `bytes`, 4 MiB by default
+    pub fn set_checkpoint_size(&mut self, bytes: u64) {
+        self.checkpoint_size = bytes;
+    }
+
+    // commit, then copy the logged writes to the inner store and empty the log
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.commit()?;
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+
+        // the commit is durable before the inner store changes,
+        // so a crash in between is replayed
+        self.log.sync()?;
+        let mut writes: Vec<(u64, u64, usize)> = self
+            .writes
+            .iter()
+            .map(|(&(start, len), &frame)| (frame, start, len))
+            .collect();
+        writes.sort_unstable();
+        for (frame, start, len) in writes {
+            let bytes = self.read_logged(frame, len)?;
+            let index = if len == 0 { 0 } else { start / len as u64 };
+            write_all_page(&mut self.inner, index, &bytes)?;
+        }
+        self.inner.sync()?;
+        self.writes.clear();
+        self.max_write = 0;
+        self.start_generation(self.generation + 1)
+    }
+
+    // checkpoint if the log is full and everything in it is committed
+    fn checkpoint_if_full(&mut self) -> io::Result<()> {
+        if !self.is_uncommitted && self.next_frame * FRAME_SIZE as u64 >= self.checkpoint_size {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    // inner store and log, writes after the last checkpoint are in the log
+    pub fn into_inner(self) -> (S, L) {
+        (self.inner, self.log)
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        if self.is_failed {
+            return Err(failed());
+        }
+        if !self.is_uncommitted {
+            return Ok(());
+        }
+        self.append(COMMIT, 0, &[])?;
+        self.is_uncommitted = false;
+        Ok(())
+    }
+
+    // log a write of `bytes` to page `index` as frames of `kind`
+    fn append(&mut self, kind: u8, index: u64, bytes: &[u8]) -> io::Result<()> {
+        let mut frame = self.next_frame;
+        let mut kind = kind;
+        let mut chunks = bytes.chunks(FRAME_DATA);
+        loop {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let bytes = self.encode_frame(kind, index, bytes.len() as u32, chunk);
+            write_all_page(&mut self.log, frame, &bytes)?;
+            frame += 1;
+            kind = MORE;
+            if chunks.len() == 0 {
+                break;
+            }
+        }
+        self.next_frame = frame;
+        Ok(())
+    }
+
+    fn encode_frame(&self, kind: u8, index: u64, len: u32, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; FRAME_SIZE];
+        frame[4..12].copy_from_slice(&self.generation.to_le_bytes());
+        frame[12] = kind;
+        frame[16..24].copy_from_slice(&index.to_le_bytes());
+        frame[24..28].copy_from_slice(&len.to_le_bytes());
+        frame[FRAME_HEADER..FRAME_HEADER + data.len()].copy_from_slice(data);
+
+        let checksum = crc32c(&frame[4..]);
+        frame[0..4].copy_from_slice(&checksum.to_le_bytes());
+        frame
+    }
+
+    // frame `index` if it is whole and of the current generation
+    fn read_frame(&mut self, index: u64) -> io::Result<Option<Vec<u8>>> {
+        let mut frame = vec![0u8; FRAME_SIZE];
+        match read_exact_page(&mut self.log, index, &mut frame) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let checksum = u32::from_le_bytes(read(&frame, 0));
+        let generation = u64::from_le_bytes(read(&frame, 4));
+        if checksum != crc32c(&frame[4..]) || generation != self.generation {
+            return Ok(None);
+        }
+        Ok(Some(frame))
+    }
+
+    // bytes of a write of `len` bytes logged from `frame` on
+    fn read_logged(&mut self, frame: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut buf = vec![0u8; FRAME_SIZE];
+        let mut frame = frame;
+        while bytes.len() < len {
+            read_exact_page(&mut self.log, frame, &mut buf)?;
+            let more = (len - bytes.len()).min(FRAME_DATA);
+            bytes.extend_from_slice(&buf[FRAME_HEADER..FRAME_HEADER + more]);
+            frame += 1;
+        }
+        Ok(bytes)
+    }
+
+    // generation in header copy `frame`, `None` if it is torn or missing
+    fn read_header(&mut self, frame: u64) -> io::Result<Option<u64>> {
+        let mut header = vec![0u8; FRAME_SIZE];
+        match read_exact_page(&mut self.log, frame, &mut header) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let checksum = u32::from_le_bytes(read(&header, 0));
+        if checksum != crc32c(&header[4..]) || &header[12..16] != SIGNATURE {
+            return Ok(None);
+        }
+        Ok(Some(u64::from_le_bytes(read(&header, 4))))
+    }
+
+    // committed writes of the current generation in order
+    fn replay(&mut self) -> io::Result<Vec<LoggedWrite>> {
+        let mut committed = Vec::new();
+        let mut pending = Vec::new();
+        // page index, length, bytes seen and first frame of a write spanning frames
+        let mut current: Option<(u64, usize, usize, u64)> = None;
+        let mut index = FIRST_RECORD_FRAME;
+
+        while let Some(frame) = self.read_frame(index)? {
+            let frame_index = index;
+            index += 1;
+            match frame[12] {
+                WRITE if current.is_none() => {
+                    let page = u64::from_le_bytes(read(&frame, 16));
+                    let len = u32::from_le_bytes(read(&frame, 24)) as usize;
+                    current = Some((page, len, len.min(FRAME_DATA), frame_index));
+                }
+                MORE if current.is_some() => {
+                    let (_, len, seen, _) = current.as_mut().unwrap();
+                    *seen += (*len - *seen).min(FRAME_DATA);
+                }
+                COMMIT if current.is_none() => committed.append(&mut pending),
+                // a frame that doesn't follow, the log ends here
+                _ => break,
+            }
+
+            if let Some((page, len, seen, first)) = current {
+                if seen == len {
+                    pending.push((page, len, first));
+                    current = None;
+                }
+            }
+        }
+        Ok(committed)
+    }
+
+    // write both header copies for `generation`, frames after them are stale
+    fn start_generation(&mut self, generation: u64) -> io::Result<()> {
+        let mut header = vec![0u8; FRAME_SIZE];
+        header[4..12].copy_from_slice(&generation.to_le_bytes());
+        header[12..16].copy_from_slice(SIGNATURE);
+        let checksum = crc32c(&header[4..]);
+        header[0..4].copy_from_slice(&checksum.to_le_bytes());
+
+        // one after the other, so a crash tears at most one
+        write_all_page(&mut self.log, 0, &header)?;
+        self.log.sync()?;
+        write_all_page(&mut self.log, 1, &header)?;
+        self.log.sync()?;
+
+        self.generation = generation;
+        self.next_frame = FIRST_RECORD_FRAME;
+        Ok(())
+    }
+}
+
+impl<S, L> PageStore for WalStore<S, L>
+where
+    S: PageStore,
+    L: PageStore,
+{
+    fn read_page(&mut self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = page_offset(index, buf.len())?;
+        let inner_len = self.inner.len()?;
+        let read = self.inner.read_page(index, buf)?;
+        buf[read..].fill(0);
+
+        // logged writes over the inner page, later ones last
+        let end = offset.saturating_add(buf.len() as u64);
+        let first = offset.saturating_sub(self.max_write as u64);
+        let mut overlapping: Vec<(u64, u64, usize)> = self
+            .writes
+            .range((first, 0)..(end, 0))
+            .filter(|(&(start, len), _)| start + len as u64 > offset)
+            .map(|(&(start, len), &frame)| (frame, start, len))
+            .collect();
+        overlapping.sort_unstable();
+        for (frame, start, len) in overlapping {
+            let bytes = self.read_logged(frame, len)?;
+            let stop = start + len as u64;
+            let from = start.max(offset);
+            let to = stop.min(end);
+            buf[(from - offset) as usize..(to - offset) as usize]
+                .copy_from_slice(&bytes[(from - start) as usize..(to - start) as usize]);
+        }
+
+        // a short read before the end of the inner store is retried
+        let inner_part = inner_len.saturating_sub(offset).min(buf.len() as u64) as usize;
+        if read < inner_part {
+            return Ok(read);
+        }
+        let len = inner_len.max(self.len);
+        Ok(len.saturating_sub(offset).min(buf.len() as u64) as usize)
+    }
+
+    fn write_page(&mut self, index: u64, buf: &[u8]) -> io::Result<usize> {
+        if self.is_failed {
+            return Err(failed());
+        }
+        let start = page_offset(index, buf.len())?;
+        let stop = start + buf.len() as u64;
+        self.checkpoint_if_full()?;
+
+        let frame = self.next_frame;
+        if let Err(e) = self.append(WRITE, index, buf) {
+            self.is_failed = true;
+            return Err(e);
+        }
+        self.is_uncommitted = true;
+
+        // earlier writes inside this one are never read again
+        let covered: Vec<(u64, usize)> = self
+            .writes
+            .range((start, 0)..(stop, 0))
+            .map(|(&key, _)| key)
+            .filter(|&(write_start, len)| write_start + len as u64 <= stop)
+            .collect();
+        for key in covered {
+            self.writes.remove(&key);
+        }
+        self.writes.insert((start, buf.len()), frame);
+        self.max_write = self.max_write.max(buf.len());
+        self.len = self.len.max(stop);
+        Ok(buf.len())
+    }
+
+    // durable once in the log, the inner store catches up at checkpoints
+    fn sync(&mut self) -> io::Result<()> {
+        self.commit()?;
+        self.log.sync()
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.inner.len()?.max(self.len))
+    }
+
+    // commits the writes so far
+    fn flush(&mut self) -> io::Result<()> {
+        self.commit()?;
+        self.checkpoint_if_full()?;
+        self.log.flush()
+    }
+}
+
+fn failed() -> io::Error {
+    io::Error::other("An earlier write to the log failed, open the store again")
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N]
+        .try_into()
+        .expect("Log field out of bounds")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WalStore, FRAME_SIZE};
+    use crate::page_store::{MemoryStore, PageStore};
+
+    fn page(store: &mut dyn PageStore, index: u64) -> Vec<u8> {
+        let mut buf = vec![0u8; 100];
+        store.read_page(index, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn committed_writes_are_replayed() {
+        let mut store = WalStore::open(MemoryStore::new(), MemoryStore::new()).unwrap();
+        store.write_page(0, &vec![2; 1000]).unwrap();
+        store.write_page(3, &[1; 100]).unwrap();
+        store.flush().unwrap();
+        store.write_page(12, &[3; 100]).unwrap();
+
+        // logged writes are read back before they reach the inner store
+        assert_eq!(page(&mut store, 3), vec![1; 100]);
+        assert_eq!(page(&mut store, 12), vec![3; 100]);
+        assert_eq!(store.len().unwrap(), 1300);
+        assert!(store.get_ref().clone().is_empty().unwrap());
+
+        let (inner, log) = store.into_inner();
+        let mut store = WalStore::open(inner, log).unwrap();
+        assert_eq!(page(&mut store, 3), vec![1; 100]);
+        assert_eq!(page(&mut store, 9), vec![2; 100]);
+        // never committed
+        assert_eq!(page(&mut store, 12), vec![0; 100]);
+        assert_eq!(store.len().unwrap(), 1000);
+    }
+
+    #[test]
+    fn checkpoint_empties_the_log() {
+        let mut store = WalStore::open(MemoryStore::new(), MemoryStore::new()).unwrap();
+        store.set_checkpoint_size(8 * FRAME_SIZE as u64);
+        for i in 0..20 {
+            store.write_page(i, &[i as u8; 100]).unwrap();
+            store.flush().unwrap();
+        }
+        // pages written before the last checkpoint are in the inner store
+        let mut inner = store.get_ref().clone();
+        assert_eq!(page(&mut inner, 5), vec![5; 100]);
+
+        let (inner, log) = store.into_inner();
+        let mut store = WalStore::open(inner, log).unwrap();
+        for i in 0..20 {
+            assert_eq!(page(&mut store, i), vec![i as u8; 100]);
+        }
+    }
+
+    #[test]
+    fn write_checkpoints_a_full_committed_log() {
+        let mut store = WalStore::open(MemoryStore::new(), MemoryStore::new()).unwrap();
+        store.set_checkpoint_size(8 * FRAME_SIZE as u64);
+        // uncommitted writes stay in the log however long it gets
+        for i in 0..10 {
+            store.write_page(i, &[i as u8 + 1; 100]).unwrap();
+        }
+        assert!(store.get_ref().clone().is_empty().unwrap());
+        assert_eq!(store.writes.len(), 10);
+
+        store.sync().unwrap();
+        store.write_page(10, &[11; 100]).unwrap();
+        let mut inner = store.get_ref().clone();
+        assert_eq!(page(&mut inner, 5), vec![6; 100]);
+        assert_eq!(store.writes.len(), 1);
+        for i in 0..11 {
+            assert_eq!(page(&mut store, i), vec![i as u8 + 1; 100]);
+        }
+    }
+
+    #[test]
+    fn overlapping_writes_are_read_back_in_order() {
+        let mut store = WalStore::open(MemoryStore::new(), MemoryStore::new()).unwrap();
+        store.write_page(0, &[1; 100]).unwrap();
+        store.write_page(1, &[2; 50]).unwrap();
+        store.write_page(0, &[3; 25]).unwrap();
+        let expected = [vec![3; 25], vec![1; 25], vec![2; 50]].concat();
+        assert_eq!(page(&mut store, 0), expected);
+        store.flush().unwrap();
+
+        let (inner, log) = store.into_inner();
+        let mut store = WalStore::open(inner, log).unwrap();
+        assert_eq!(page(&mut store, 0), expected);
+    }
+}
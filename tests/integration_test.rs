@@ -1,4 +1,10 @@
-use vmem::VirtualMemory;
+use proptest::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use vmem::{
+    policy, Checksum, Fault, FaultyStore, MemoryStore, Options, PageStore, Pod, ReplacementPolicy,
+    RwsStore, SharedVirtualMemory, UnsetBytes, VirtualMemory, WalStore,
+};
 
 #[test]
 fn swap_pages_in_buffer() {
@@ -30,3 +36,558 @@ fn swap_pages_in_buffer() {
     assert_eq!(vm.read(2), Some(2));
     assert_eq!(vm.read(4), Some(3));
 }
+
+#[test]
+fn reopen_swap_file() {
+    let mut swap_file = tempfile::tempfile().unwrap();
+
+    {
+        let mut vm = VirtualMemory::new(&mut swap_file, 9, 3);
+        for i in 0..64 {
+            vm.write(i, i as u8);
+        }
+    }
+
+    let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+    for i in 0..64 {
+        assert_eq!(vm.read(i), Some(i as u8));
+    }
+    assert_eq!(vm.read(64), None);
+}
+
+fn keeps_values_with<P: ReplacementPolicy + 'static>(policy: P) {
+    let swap_file = tempfile::tempfile().unwrap();
+    let mut vm = VirtualMemory::new(swap_file, 9, 3);
+    vm.set_policy(policy);
+
+    // 8 values per page, 16 pages through a buffer of 3
+    for i in 0..128 {
+        vm.write(i, (i * 7) as u8);
+    }
+    for i in (0..128).rev() {
+        assert_eq!(vm.read(i), Some((i * 7) as u8));
+    }
+}
+
+#[test]
+fn every_policy_keeps_values() {
+    keeps_values_with(policy::Lru::new());
+    keeps_values_with(policy::Fifo::new());
+    keeps_values_with(policy::Clock::new());
+    keeps_values_with(policy::Lfu::new());
+    keeps_values_with(policy::Random::new(7));
+}
+
+#[test]
+fn slices_through_evictions() {
+    let swap_file = tempfile::tempfile().unwrap();
+    let mut vm = VirtualMemory::new(swap_file, 9, 3);
+
+    // 1000 bytes over 125 pages of 8 values, buffer holds 3
+    let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    vm.write_slice(5, &data);
+
+    let mut buf = vec![0; 1010];
+    let unset = vm.read_into(0, &mut buf);
+    assert_eq!(unset, vec![0..5, 1005..1010]);
+    assert_eq!(&buf[5..1005], data.as_slice());
+
+    for (i, byte) in data.iter().enumerate() {
+        assert_eq!(vm.read(i + 5), Some(*byte));
+    }
+}
+
+#[test]
+fn cursor_as_serialization_backend() {
+    let swap_file = tempfile::tempfile().unwrap();
+    let mut vm = VirtualMemory::new(swap_file, 64, 3);
+
+    let records: Vec<(u32, String)> = (0..50).map(|i| (i, format!("record {}", i))).collect();
+    let mut cursor = vm.cursor(UnsetBytes::Error);
+    bincode::serialize_into(&mut cursor, &records).unwrap();
+
+    cursor.seek(SeekFrom::Start(0)).unwrap();
+    let decoded: Vec<(u32, String)> = bincode::deserialize_from(&mut cursor).unwrap();
+    assert_eq!(decoded, records);
+
+    cursor.seek(SeekFrom::Start(0)).unwrap();
+    let mut copy = Vec::new();
+    std::io::copy(&mut cursor, &mut copy).unwrap();
+    assert_eq!(copy, bincode::serialize(&records).unwrap());
+}
+
+#[test]
+fn remove_leaves_neighbours_in_place() {
+    let swap_file = tempfile::tempfile().unwrap();
+    let mut vm = VirtualMemory::new(swap_file, 9, 3);
+
+    for i in 0..64 {
+        vm.write(i, i as u8 + 1);
+    }
+    // holes at the start, middle and end of pages
+    for i in [0, 10, 11, 23, 63] {
+        assert_eq!(vm.remove(i), Some(i as u8 + 1));
+        assert_eq!(vm.remove(i), None);
+    }
+
+    // cycle every page through the swap file, twice
+    for _ in 0..2 {
+        for i in (0..64).step_by(8) {
+            vm.read(i);
+        }
+    }
+
+    for i in 0..64 {
+        let expected = match i {
+            0 | 10 | 11 | 23 | 63 => None,
+            _ => Some(i as u8 + 1),
+        };
+        assert_eq!(vm.read(i), expected, "index {}", i);
+    }
+}
+
+#[test]
+fn remove_survives_reopen() {
+    let mut swap_file = tempfile::tempfile().unwrap();
+    {
+        let mut vm = VirtualMemory::new(&mut swap_file, 9, 3);
+        vm.write_slice(0, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        vm.remove(2);
+        vm.remove(8);
+    }
+
+    let mut vm = VirtualMemory::open(&mut swap_file, 3).unwrap();
+    let values = vm.read_vec(0..10);
+    assert_eq!(
+        values,
+        vec![
+            Some(1),
+            Some(2),
+            None,
+            Some(4),
+            Some(5),
+            Some(6),
+            Some(7),
+            Some(8),
+            None,
+            Some(10)
+        ]
+    );
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+struct Sample {
+    timestamp: u64,
+    value: f64,
+    channel: u32,
+    flags: u32,
+}
+
+unsafe impl Pod for Sample {}
+
+#[test]
+fn typed_values() {
+    let mut swap_file = tempfile::tempfile().unwrap();
+    let samples: Vec<Sample> = (0..100)
+        .map(|i| Sample {
+            timestamp: 1_000 + i,
+            value: i as f64 / 4.0,
+            channel: (i % 3) as u32,
+            flags: 0,
+        })
+        .collect();
+
+    {
+        // 24 byte values, 7 per page of 170 bytes
+        let mut vm = VirtualMemory::<_, Sample>::try_new_typed(&mut swap_file, 170, 3).unwrap();
+        vm.write_slice(0, &samples[..50]);
+        for (i, sample) in samples.iter().enumerate().skip(50) {
+            vm.write(i, *sample);
+        }
+        assert_eq!(vm.remove(3), Some(samples[3]));
+    }
+
+    // other value types can't open the file
+    assert!(VirtualMemory::<_, u64>::open_typed(&mut swap_file, 3).is_err());
+
+    let mut vm = VirtualMemory::<_, Sample>::open_typed(&mut swap_file, 3).unwrap();
+    assert_eq!(vm.len(), 100);
+    assert_eq!(vm.read(3), None);
+    let read = vm.read_vec(0..100);
+    for (i, sample) in samples.iter().enumerate() {
+        if i != 3 {
+            assert_eq!(read[i], Some(*sample));
+        }
+    }
+}
+
+#[test]
+fn typed_numbers() {
+    let swap_file = tempfile::tempfile().unwrap();
+    let mut vm = VirtualMemory::<_, f64>::try_new_typed(swap_file, 64, 3).unwrap();
+    for i in 0..200 {
+        vm.write(i, (i as f64).sqrt());
+    }
+    for i in (0..200).rev() {
+        assert_eq!(vm.read(i), Some((i as f64).sqrt()));
+    }
+}
+
+#[test]
+fn shared_memory_is_interchangeable() {
+    let mut swap_file = tempfile::tempfile().unwrap();
+    {
+        let vm = SharedVirtualMemory::<_, u64>::try_new_typed(&swap_file, 128, 8, 4).unwrap();
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let vm = &vm;
+                s.spawn(move || {
+                    for i in (t * 1000..(t + 1) * 1000).rev() {
+                        vm.write(i, i as u64 * 11);
+                    }
+                });
+            }
+        });
+    }
+
+    // threads wrote through the shared memory, a single owner reads it back
+    let mut vm = VirtualMemory::<_, u64>::open_typed(&mut swap_file, 3).unwrap();
+    assert_eq!(vm.len(), 4000);
+    let values = vm.read_vec(0..4000);
+    for (i, value) in values.into_iter().enumerate() {
+        assert_eq!(value, Some(i as u64 * 11));
+    }
+}
+
+#[test]
+fn checksummed_pages_in_shared_memory() {
+    let mut swap_file = tempfile::tempfile().unwrap();
+    let options = Options {
+        checksum: Checksum::XxHash64,
+        ..Options::default()
+    };
+    {
+        let mut vm =
+            VirtualMemory::<_, u32>::try_new_with_options(&mut swap_file, 64, 3, options).unwrap();
+        for i in 0..500 {
+            vm.write(i, i as u32);
+        }
+    }
+
+    // the shared memory checks and writes the same checksums
+    {
+        let vm = SharedVirtualMemory::<_, u32>::open_typed(&swap_file, 4, 2).unwrap();
+        for i in 0..500 {
+            assert_eq!(vm.read(i), Some(i as u32));
+        }
+        vm.write(3, 33);
+    }
+
+    let mut vm = VirtualMemory::<_, u32>::open_typed(&mut swap_file, 3).unwrap();
+    assert_eq!(vm.read(3), Some(33));
+    assert_eq!(vm.read(499), Some(499));
+}
+
+// small values, then pages of noise that don't compress and take
+// larger blocks, all found again after reopening
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+fn compressed_pages(compression: vmem::Compression) {
+    let mut store = MemoryStore::new();
+    let options = Options {
+        compression,
+        ..Options::default()
+    };
+    let mut noise = 0x2545_f491_4f6c_dd1du64;
+    let mut expected: Vec<u64> = (0..20_000).map(|i| i / 7).collect();
+    {
+        let mut vm =
+            VirtualMemory::<_, u64>::try_new_with_options(&mut store, 4096, 3, options).unwrap();
+        vm.write_slice(0, &expected);
+        vm.flush().unwrap();
+        assert!(vm.stats().compression_ratio() > 4.0);
+
+        for value in &mut expected[3000..6000] {
+            noise ^= noise << 13;
+            noise ^= noise >> 7;
+            noise ^= noise << 17;
+            *value = noise;
+        }
+        vm.write_slice(3000, &expected[3000..6000]);
+    }
+
+    let mut vm = VirtualMemory::<_, u64>::open_typed(&mut store, 3).unwrap();
+    assert_eq!(vm.len(), expected.len());
+    let values = vm.read_vec(0..expected.len());
+    for (value, expected) in values.into_iter().zip(&expected) {
+        assert_eq!(value, Some(*expected));
+    }
+}
+
+#[test]
+#[cfg(feature = "lz4")]
+fn lz4_pages() {
+    compressed_pages(vmem::Compression::Lz4);
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn zstd_pages() {
+    compressed_pages(vmem::Compression::Zstd);
+}
+
+// pages are compressed, then sealed, and the checksum inside the seal
+// is still checked after opening
+#[test]
+#[cfg(all(feature = "chacha20poly1305", feature = "lz4"))]
+fn encrypted_compressed_pages() {
+    let key = [3u8; 32];
+    let options = Options {
+        checksum: Checksum::Crc32c,
+        compression: vmem::Compression::Lz4,
+        encryption: vmem::Encryption::ChaCha20Poly1305,
+    };
+    let mut store = MemoryStore::new();
+    {
+        let mut vm =
+            VirtualMemory::<_, u32>::try_new_with_key(&mut store, 1024, 3, options, &key).unwrap();
+        for i in 0..5000 {
+            vm.write(i, i as u32 % 10);
+        }
+        vm.flush().unwrap();
+        assert!(vm.stats().compression_ratio() > 2.0);
+    }
+
+    let mut vm = VirtualMemory::<_, u32>::open_with_key(&mut store, 3, &key).unwrap();
+    for i in 0..5000 {
+        assert_eq!(vm.read(i), Some(i as u32 % 10));
+    }
+}
+
+// every value of one pass over 64 elements, all in one flush
+fn write_pass<S: PageStore>(vm: &mut VirtualMemory<S>, pass: u8) -> vmem::Result<()> {
+    for i in 0..64 {
+        vm.try_write(i, pass * 50 + i as u8)?;
+    }
+    vm.flush()
+}
+
+// the pass the swap source is as of, never a mix of two
+fn recovered_pass(inner: &mut MemoryStore, log: &mut MemoryStore) -> u8 {
+    let mut wal = WalStore::open(inner, log).unwrap();
+    let mut vm = VirtualMemory::open(&mut wal, 3).unwrap();
+    let values: Vec<_> = (0..64).map(|i| vm.read(i)).collect();
+    for pass in [1, 2] {
+        let expected: Vec<_> = (0..64).map(|i| Some(pass * 50 + i as u8)).collect();
+        if values == expected {
+            return pass;
+        }
+    }
+    panic!("torn swap source: {values:?}");
+}
+
+// pass 1 checkpointed to the inner store, pass 2 committed to the log
+fn wal_stores() -> [(MemoryStore, MemoryStore); 2] {
+    let mut wal = WalStore::open(MemoryStore::new(), MemoryStore::new()).unwrap();
+    {
+        let mut vm = VirtualMemory::try_new(&mut wal, 16, 3).unwrap();
+        write_pass(&mut vm, 1).unwrap();
+    }
+    wal.checkpoint().unwrap();
+    let first = wal.into_inner();
+
+    let (inner, log) = first.clone();
+    let mut wal = WalStore::open(inner, log).unwrap();
+    {
+        let mut vm = VirtualMemory::open(&mut wal, 3).unwrap();
+        write_pass(&mut vm, 2).unwrap();
+    }
+    [first, wal.into_inner()]
+}
+
+// the log dies after every number of bytes while pass 2 is written,
+// the swap source is as of pass 1 until the commit and pass 2 after it
+#[test]
+fn wal_crash_while_logging() {
+    let [(inner, log), _] = wal_stores();
+    let mut committed = false;
+    for crash in 0.. {
+        let mut inner = inner.clone();
+        let mut log = FaultyStore::new(log.clone());
+        log.crash_after(crash);
+        // opening starts a new generation, the crash can come first
+        if let Ok(mut wal) = WalStore::open(&mut inner, &mut log) {
+            let mut vm = VirtualMemory::open(&mut wal, 3).unwrap();
+            let _ = write_pass(&mut vm, 2);
+        }
+        let is_crashed = log.is_crashed();
+
+        let pass = recovered_pass(&mut inner, &mut log.into_inner());
+        // once committed, a later crash never loses pass 2
+        assert!(pass == 2 || !committed);
+        committed = pass == 2;
+        if !is_crashed {
+            assert_eq!(pass, 2);
+            break;
+        }
+    }
+}
+
+// the inner store dies after every number of bytes while the log of
+// pass 2 is replayed, which is redone on the next open
+#[test]
+fn wal_crash_while_replaying() {
+    let [_, (inner, log)] = wal_stores();
+    for crash in 0.. {
+        let mut inner = FaultyStore::new(inner.clone());
+        let mut log = log.clone();
+        inner.crash_after(crash);
+        let is_opened = WalStore::open(&mut inner, &mut log).is_ok();
+        assert_eq!(is_opened, !inner.is_crashed());
+
+        assert_eq!(recovered_pass(&mut inner.into_inner(), &mut log), 2);
+        if is_opened {
+            break;
+        }
+    }
+}
+
+// a crash tore the first frame after the header, the frames after it
+// must not count for writes of the next run
+#[test]
+fn wal_torn_first_frame() {
+    let mut wal = WalStore::open(MemoryStore::new(), MemoryStore::new()).unwrap();
+    wal.write_page(0, &[1; 100]).unwrap();
+    wal.flush().unwrap();
+    let (inner, mut log) = wal.into_inner();
+    // the write frame is lost, its commit frame made it
+    log.write_page(2, &[0; 512]).unwrap();
+
+    let mut wal = WalStore::open(inner, log).unwrap();
+    wal.write_page(0, &[9; 100]).unwrap();
+    let (inner, log) = wal.into_inner();
+
+    let mut wal = WalStore::open(inner, log).unwrap();
+    let mut page = vec![0u8; 100];
+    wal.read_page(0, &mut page).unwrap();
+    assert_eq!(page, vec![0; 100]);
+}
+
+// `Read + Write + Seek` source that transfers a random part of every
+// buffer and is sometimes interrupted
+struct ShortIo {
+    inner: Cursor<Vec<u8>>,
+    seed: u64,
+}
+
+impl ShortIo {
+    // xorshift, deterministic for a proptest seed
+    fn next(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    // bytes to transfer out of `len`, `None` to be interrupted
+    fn count(&mut self, len: usize) -> Option<usize> {
+        if len == 0 {
+            return Some(0);
+        }
+        let random = self.next();
+        if random.is_multiple_of(4) {
+            return None;
+        }
+        Some(1 + (random >> 2) as usize % len)
+    }
+}
+
+impl Read for ShortIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.count(buf.len()) {
+            Some(len) => self.inner.read(&mut buf[..len]),
+            None => Err(io::ErrorKind::Interrupted.into()),
+        }
+    }
+}
+
+impl Write for ShortIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.count(buf.len()) {
+            Some(len) => self.inner.write(&buf[..len]),
+            None => Err(io::ErrorKind::Interrupted.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ShortIo {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// writes and removes through `store`, then checks the contents before
+// and after reopening it
+fn check_model<S: PageStore>(mut store: S, ops: &[(usize, Option<u8>)]) -> S {
+    let mut model = HashMap::new();
+    {
+        let mut vm = VirtualMemory::new(&mut store, 16, 3);
+        for &(index, value) in ops {
+            match value {
+                Some(value) => {
+                    vm.write(index, value);
+                    model.insert(index, value);
+                }
+                None => {
+                    assert_eq!(vm.remove(index), model.remove(&index));
+                }
+            }
+        }
+        for index in 0..vm.len() {
+            assert_eq!(vm.read(index), model.get(&index).copied());
+        }
+    }
+
+    let mut vm = VirtualMemory::open(&mut store, 3).unwrap();
+    for index in 0..vm.len() {
+        assert_eq!(vm.read(index), model.get(&index).copied());
+    }
+    drop(vm);
+    store
+}
+
+fn ops() -> impl Strategy<Value = Vec<(usize, Option<u8>)>> {
+    prop::collection::vec(
+        (0usize..300, prop::option::weighted(0.8, any::<u8>())),
+        1..200,
+    )
+}
+
+proptest! {
+    #[test]
+    fn random_short_transfers(ops in ops(), seed in 1u64..) {
+        let store = RwsStore::new(ShortIo { inner: Cursor::new(Vec::new()), seed });
+        check_model(store, &ops);
+    }
+
+    #[test]
+    fn scheduled_short_pages(
+        ops in ops(),
+        faults in prop::collection::vec(
+            (0u64..200, prop_oneof![(0usize..16).prop_map(Fault::Short), Just(Fault::Interrupted)]),
+            0..40,
+        ),
+    ) {
+        let mut store = FaultyStore::new(MemoryStore::new());
+        // faults on even calls only, so every page gets through on a retry
+        for &(call, fault) in &faults {
+            store.fail_read(call * 2, fault);
+            store.fail_write(call * 2, fault);
+        }
+        check_model(store, &ops);
+    }
+}